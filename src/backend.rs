@@ -0,0 +1,130 @@
+//! Runs a script on either backend and records what it did, so that the
+//! tree-walker and the VM can be checked against each other.
+//!
+//! ```
+//! use rlox::backend::{self, Backend};
+//!
+//! let tree = backend::run(Backend::Tree, "print 1 + 2;", false, false);
+//! let vm = backend::run(Backend::Vm, "print 1 + 2;", false, false);
+//! assert_eq!(tree.output, "3\n");
+//! assert_eq!(tree, vm);
+//! ```
+
+use std::{fmt::Display, str::FromStr};
+
+use crate::{
+    compiler::Compiler,
+    interpreter::{diagnostic::Diagnostic, Interpreter},
+    output::Output,
+    vm::VM,
+};
+
+/// The two engines a script can run on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// The tree-walking [`Interpreter`]
+    Tree,
+    /// The bytecode [`VM`]
+    Vm,
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Backend::Tree => write!(f, "tree"),
+            Backend::Vm => write!(f, "vm"),
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "tree" => Ok(Backend::Tree),
+            "vm" => Ok(Backend::Vm),
+            _ => Err(format!("Unknown backend '{}', expected tree or vm", name)),
+        }
+    }
+}
+
+/// An error a script stopped with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportedError {
+    /// The line the error points at, if it has a location
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl From<&Diagnostic> for ReportedError {
+    fn from(diagnostic: &Diagnostic) -> Self {
+        Self {
+            line: Some(diagnostic.range.line),
+            message: diagnostic.message.clone(),
+        }
+    }
+}
+
+/// What a script did when it ran.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    /// Everything the script printed
+    pub output: String,
+    /// The exit status `rlox run` ends with, 0 if the script succeeded
+    pub status: i32,
+    /// The errors the script stopped with, in the order they were reported
+    pub errors: Vec<ReportedError>,
+}
+
+/// Runs `source` on `backend` with the standard native functions. `optimize`
+/// runs the peephole optimizer over the bytecode and `stress_gc` makes the VM
+/// collect garbage before every instruction, the tree-walker ignores both.
+pub fn run(backend: Backend, source: &str, optimize: bool, stress_gc: bool) -> Outcome {
+    let (output, captured) = Output::capture();
+    let (status, errors) = match backend {
+        Backend::Tree => run_tree(source, output),
+        Backend::Vm => run_vm(source, output, optimize, stress_gc),
+    };
+    Outcome {
+        output: captured.contents(),
+        status,
+        errors,
+    }
+}
+
+fn run_tree(source: &str, output: Output) -> (i32, Vec<ReportedError>) {
+    let mut lox = Interpreter::new();
+    lox.set_output(output);
+    match lox.run(source) {
+        Ok(()) => (0, Vec::new()),
+        Err(err) => {
+            let errors = err.diagnostics().iter().map(ReportedError::from).collect();
+            (err.status(), errors)
+        }
+    }
+}
+
+fn run_vm(
+    source: &str,
+    output: Output,
+    optimize: bool,
+    stress_gc: bool,
+) -> (i32, Vec<ReportedError>) {
+    let mut compiler = Compiler::new();
+    if let Err(err) = compiler.compile(source) {
+        let errors = err.diagnostics().iter().map(ReportedError::from).collect();
+        return (err.status(), errors);
+    }
+    let mut program = compiler.into_program();
+    if optimize {
+        program.optimize();
+    }
+    let mut vm = VM::new(program);
+    vm.set_output(output);
+    vm.set_gc_stress(stress_gc);
+    match vm.run() {
+        Ok(()) => (0, Vec::new()),
+        Err(err) => (70, vec![ReportedError::from(&Diagnostic::from(&err))]),
+    }
+}
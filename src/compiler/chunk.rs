@@ -1,12 +1,12 @@
-use std::collections::HashMap;
-
 use super::{SourceCodeRange, Value};
 
 pub struct Chunk {
     pub(crate) code_array: Vec<u8>,
     pub(crate) constant_pool: Vec<Value>,
-    pub(crate) globals: HashMap<ustr::Ustr, Value>,
-    pub(crate) lines: Vec<SourceCodeRange>,
+    /// Where the code was compiled from, run-length encoded: every entry is
+    /// the offset of the first byte compiled from a range. An instruction and
+    /// its operands always share a range, and so do many neighbouring ones.
+    pub(crate) ranges: Vec<(usize, SourceCodeRange)>,
 }
 
 impl Chunk {
@@ -14,13 +14,20 @@ impl Chunk {
         Self {
             code_array: Vec::new(),
             constant_pool: Vec::new(),
-            globals: HashMap::new(),
-            lines: Vec::new(),
+            ranges: Vec::new(),
         }
     }
 
-    pub fn push_code(&mut self, code: u8, line: SourceCodeRange) {
+    pub fn push_code(&mut self, code: u8, range: SourceCodeRange) {
+        if self.ranges.last().map(|(_, last)| *last) != Some(range) {
+            self.ranges.push((self.code_array.len(), range));
+        }
         self.code_array.push(code);
-        self.lines.push(line);
+    }
+
+    /// The source range the byte at `offset` was compiled from.
+    pub(crate) fn range(&self, offset: usize) -> SourceCodeRange {
+        let run = self.ranges.partition_point(|(start, _)| *start <= offset);
+        self.ranges[run - 1].1
     }
 }
@@ -1,47 +1,84 @@
-use std::ptr::NonNull;
-
 use crate::compiler::op_codes::OpCode;
 
-use super::Chunk;
+use super::{Chunk, GlobalTable, Heap, Object, Program, Value};
+
+pub fn disassemble_program(program: &Program, name: &str) {
+    disassemble_chunk(&program.chunk, &program.heap, &program.globals, name);
+}
 
-pub fn disassemble_chunk(chunk: &Chunk, name: &str) {
+pub fn disassemble_chunk(chunk: &Chunk, heap: &Heap, globals: &GlobalTable, name: &str) {
     println!("== {} ==", name);
 
     let mut offset = 0;
     while offset < chunk.code_array.len() {
-        offset = disassemble_instruction(chunk, offset);
+        offset = disassemble_instruction(chunk, heap, globals, offset);
+    }
+
+    // functions declared in this chunk have their own code
+    for constant in &chunk.constant_pool {
+        if let Value::Obj(obj) = constant {
+            if let Object::Function(function) = heap.get(*obj) {
+                disassemble_chunk(&function.chunk, heap, globals, &function.to_string());
+            }
+        }
     }
 }
 
-pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
+pub fn disassemble_instruction(
+    chunk: &Chunk,
+    heap: &Heap,
+    globals: &GlobalTable,
+    offset: usize,
+) -> usize {
     print!("{:04} ", offset);
     if offset > chunk.code_array.len() {
         println!("End of chunk");
         return offset;
     }
-    if offset > 0 && chunk.lines[offset].line == chunk.lines[offset - 1].line {
+    let line = chunk.range(offset).line;
+    if offset > 0 && line == chunk.range(offset - 1).line {
         print!("   | ");
     } else {
-        print!("{:4} ", chunk.lines[offset].line);
+        print!("{:4} ", line);
     }
 
     let instruction: Result<OpCode, _> = chunk.code_array[offset].try_into();
     use OpCode::*;
     match instruction {
         Ok(OpReturn) => simple_instruction("OP_RETURN", offset),
-        Ok(OpConstant) => const_instruction(chunk, offset),
-        Ok(OpConstantLong) => const_long_instruction(chunk, offset),
+        Ok(OpConstant) => const_instruction(chunk, heap, offset),
+        Ok(OpConstantLong) => const_long_instruction(chunk, heap, offset),
         Ok(OpNegate) => simple_instruction("OP_NEGATE", offset),
         Ok(OpDefineGlobal | OpGetGlobal | OpSetGlobal) => {
-            global_instruction(chunk, &instruction.unwrap(), offset)
+            global_instruction(chunk, globals, &instruction.unwrap(), offset)
         }
         Ok(OpGetLocal | OpSetLocal) => local_instruction(chunk, offset, &instruction.unwrap()),
+        Ok(OpGetLocalLong | OpSetLocalLong) => {
+            local_long_instruction(chunk, offset, &instruction.unwrap())
+        }
         Ok(
             OpAdd | OpSubtract | OpMultiply | OpDivide | OpNil | OpFalse | OpTrue | OpNot | OpEq
-            | OpGreater | OpLess | OpPrint | OpPop,
+            | OpGreater | OpLess | OpPrint | OpPop | OpCloseUpvalue | OpInherit | OpGreaterEqual
+            | OpLessEqual | OpNotEqual,
         ) => simple_instruction(&instruction.unwrap().to_string(), offset),
         Ok(OpJump | OpJumpIfFalse | OpLoop) => {
-            jump_instruction(chunk, offset, &instruction.unwrap())
+            jump_instruction(chunk, offset, &instruction.unwrap(), 2)
+        }
+        Ok(OpJumpLong | OpJumpIfFalseLong | OpLoopLong) => {
+            jump_instruction(chunk, offset, &instruction.unwrap(), 3)
+        }
+        Ok(OpCall | OpGetUpvalue | OpSetUpvalue) => {
+            byte_instruction(chunk, offset, &instruction.unwrap())
+        }
+        Ok(OpClosure | OpClosureLong) => {
+            closure_instruction(chunk, heap, offset, &instruction.unwrap())
+        }
+        Ok(
+            OpClass | OpMethod | OpGetProperty | OpSetProperty | OpGetSuper | OpClassLong
+            | OpMethodLong | OpGetPropertyLong | OpSetPropertyLong | OpGetSuperLong,
+        ) => name_instruction(chunk, heap, offset, &instruction.unwrap()),
+        Ok(OpInvoke | OpSuperInvoke | OpInvokeLong | OpSuperInvokeLong) => {
+            invoke_instruction(chunk, heap, offset, &instruction.unwrap())
         }
         Err(_) => {
             println!("Unknown opcode {}", chunk.code_array[offset]);
@@ -50,19 +87,74 @@ pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
     }
 }
 
-fn jump_instruction(chunk: &Chunk, offset: usize, instruction: &OpCode) -> usize {
-    let mut jump: i16 =
-        (chunk.code_array[offset + 1] as i16) << 8 | chunk.code_array[offset + 2] as i16;
-    if let OpCode::OpLoop = instruction {
+/// Prints a jump whose distance is `width` bytes long.
+fn jump_instruction(chunk: &Chunk, offset: usize, instruction: &OpCode, width: usize) -> usize {
+    let next = offset + 1 + width;
+    let mut jump = chunk.code_array[offset + 1..next]
+        .iter()
+        .fold(0, |jump, &byte| jump << 8 | byte as isize);
+    if let OpCode::OpLoop | OpCode::OpLoopLong = instruction {
         jump = -jump;
     }
+    println!("{} {} -> {}", instruction, offset, next as isize + jump);
+    next
+}
+
+fn byte_instruction(chunk: &Chunk, offset: usize, instruction: &OpCode) -> usize {
+    let operand = chunk.code_array[offset + 1];
+    println!("{} {}", instruction, operand);
+    offset + 2
+}
+
+/// Reads the index of the constant `instruction` refers to, returning it and
+/// the offset after it.
+fn constant_operand(chunk: &Chunk, offset: usize, instruction: &OpCode) -> (usize, usize) {
+    let width = if instruction.is_long_constant() { 3 } else { 1 };
+    let next = offset + 1 + width;
+    let constant_idx = chunk.code_array[offset + 1..next]
+        .iter()
+        .fold(0, |idx, &byte| idx << 8 | byte as usize);
+    (constant_idx, next)
+}
+
+fn closure_instruction(chunk: &Chunk, heap: &Heap, offset: usize, instruction: &OpCode) -> usize {
+    let (constant_idx, mut offset) = constant_operand(chunk, offset, instruction);
+    let constant = heap.display(chunk.constant_pool[constant_idx]);
+    println!("{} {} '{}'", instruction, constant_idx, constant);
+
+    if let Value::Obj(obj) = chunk.constant_pool[constant_idx] {
+        let function = heap.function(obj);
+        for _ in 0..function.upvalue_count {
+            let kind = if chunk.code_array[offset] == 1 {
+                "local"
+            } else {
+                "upvalue"
+            };
+            let idx =
+                (chunk.code_array[offset + 1] as u16) << 8 | chunk.code_array[offset + 2] as u16;
+            println!("{:04}    |   {} {}", offset, kind, idx);
+            offset += 3;
+        }
+    }
+    offset
+}
+
+fn name_instruction(chunk: &Chunk, heap: &Heap, offset: usize, instruction: &OpCode) -> usize {
+    let (constant_idx, next) = constant_operand(chunk, offset, instruction);
+    let constant = heap.display(chunk.constant_pool[constant_idx]);
+    println!("{} {} '{}'", instruction, constant_idx, constant);
+    next
+}
+
+fn invoke_instruction(chunk: &Chunk, heap: &Heap, offset: usize, instruction: &OpCode) -> usize {
+    let (constant_idx, next) = constant_operand(chunk, offset, instruction);
+    let arg_count = chunk.code_array[next];
+    let constant = heap.display(chunk.constant_pool[constant_idx]);
     println!(
-        "{} {} -> {}",
-        instruction,
-        offset,
-        offset as isize + 3 + jump as isize
+        "{} ({} args) {} '{}'",
+        instruction, arg_count, constant_idx, constant
     );
-    offset + 3
+    next + 1
 }
 
 fn local_instruction(chunk: &Chunk, offset: usize, instruction: &OpCode) -> usize {
@@ -71,34 +163,37 @@ fn local_instruction(chunk: &Chunk, offset: usize, instruction: &OpCode) -> usiz
     offset + 2
 }
 
-fn global_instruction(chunk: &Chunk, unwrap: &OpCode, offset: usize) -> usize {
-    // the next pointer-sized bytes interpreted as a usize
-    let mut pointer_address = 0;
-    for i in 0..std::mem::size_of::<usize>() {
-        pointer_address |= (chunk.code_array[offset + 1 + i] as usize) << (i * 8);
-    }
-    let ustring = unsafe {
-        std::mem::transmute::<NonNull<u8>, ustr::Ustr>(NonNull::new_unchecked(
-            pointer_address as *mut u8,
-        ))
-    };
-    println!("{} {} '{}'", unwrap, pointer_address, ustring);
-    offset + 1 + std::mem::size_of::<usize>()
+fn local_long_instruction(chunk: &Chunk, offset: usize, instruction: &OpCode) -> usize {
+    let local_idx =
+        (chunk.code_array[offset + 1] as u16) << 8 | chunk.code_array[offset + 2] as u16;
+    println!("{} {}", instruction, local_idx);
+    offset + 3
+}
+
+fn global_instruction(
+    chunk: &Chunk,
+    globals: &GlobalTable,
+    instruction: &OpCode,
+    offset: usize,
+) -> usize {
+    let slot = (chunk.code_array[offset + 1] as u16) << 8 | chunk.code_array[offset + 2] as u16;
+    println!("{} {} '{}'", instruction, slot, globals.name(slot));
+    offset + 3
 }
 
-fn const_instruction(chunk: &Chunk, offset: usize) -> usize {
+fn const_instruction(chunk: &Chunk, heap: &Heap, offset: usize) -> usize {
     let constant_idx = chunk.code_array[offset + 1];
-    let constant = &chunk.constant_pool[constant_idx as usize];
+    let constant = heap.display(chunk.constant_pool[constant_idx as usize]);
     println!("OP_CONSTANT {} '{}'", constant_idx, constant);
     offset + 2
 }
 
-fn const_long_instruction(chunk: &Chunk, offset: usize) -> usize {
+fn const_long_instruction(chunk: &Chunk, heap: &Heap, offset: usize) -> usize {
     // the next 24 bits interpreted as a u32
     let constant_idx = (chunk.code_array[offset + 1] as u32) << 16
         | (chunk.code_array[offset + 2] as u32) << 8
         | chunk.code_array[offset + 3] as u32;
-    let constant = &chunk.constant_pool[constant_idx as usize];
+    let constant = heap.display(chunk.constant_pool[constant_idx as usize]);
     println!("OP_CONSTANT_LONG {} '{}'", constant_idx, constant);
     offset + 4
 }
@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+
+/// The global variables of a program. The compiler resolves every global to
+/// its slot in this table, so the VM stores them in a plain vector and only
+/// needs the names to report errors.
+#[derive(Debug, Default)]
+pub struct GlobalTable {
+    names: Vec<String>,
+    slots: HashMap<String, u16>,
+}
+
+impl GlobalTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// The slot of the global `name`, if the program refers to it.
+    pub fn find(&self, name: &str) -> Option<u16> {
+        self.slots.get(name).copied()
+    }
+
+    pub fn name(&self, slot: u16) -> &str {
+        &self.names[slot as usize]
+    }
+
+    /// Returns the slot of the global `name`, adding it if it is new. Fails
+    /// once every slot addressable by an operand is taken.
+    pub(crate) fn resolve(&mut self, name: &str) -> Option<u16> {
+        if let Some(slot) = self.find(name) {
+            return Some(slot);
+        }
+        let slot = self.names.len().try_into().ok()?;
+        self.names.push(name.to_string());
+        self.slots.insert(name.to_string(), slot);
+        Some(slot)
+    }
+}
@@ -0,0 +1,412 @@
+use std::{collections::HashMap, fmt::Display, rc::Rc};
+
+use super::values::{BoundMethod, Class, Closure, Function, Instance, Upvalue, Value};
+use crate::natives::{NativeFunction, NativeValue};
+
+/// How many bytes may be allocated before the first collection.
+const INITIAL_GC_THRESHOLD: usize = 1024 * 1024;
+/// After a collection, the next one happens once the heap has grown by this factor.
+const GC_HEAP_GROW_FACTOR: usize = 2;
+
+/// A handle to an object on a [`Heap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjRef(usize);
+
+pub enum Object {
+    String(String),
+    /// Functions never change once compiled, so call frames can share them
+    Function(Rc<Function>),
+    Native(NativeFunction),
+    Closure(Closure),
+    Upvalue(Upvalue),
+    Class(Class),
+    Instance(Instance),
+    BoundMethod(BoundMethod),
+}
+
+impl Object {
+    /// Roughly how many bytes the object occupies, to decide when to collect.
+    fn size(&self) -> usize {
+        let contents = match self {
+            Object::String(s) => s.len(),
+            Object::Function(function) => {
+                function.chunk.code_array.len()
+                    + function.chunk.constant_pool.len() * std::mem::size_of::<Value>()
+            }
+            Object::Closure(closure) => closure.upvalues.len() * std::mem::size_of::<ObjRef>(),
+            Object::Class(class) => class.methods.len() * 2 * std::mem::size_of::<ObjRef>(),
+            Object::Instance(instance) => {
+                instance.fields.len()
+                    * (std::mem::size_of::<ObjRef>() + std::mem::size_of::<Value>())
+            }
+            Object::Native(_) | Object::Upvalue(_) | Object::BoundMethod(_) => 0,
+        };
+        std::mem::size_of::<Object>() + contents
+    }
+
+    /// Calls `visit` with every object this object keeps alive.
+    fn trace(&self, mut visit: impl FnMut(ObjRef)) {
+        let mut visit_value = |value: &Value| {
+            if let Value::Obj(obj) = value {
+                visit(*obj);
+            }
+        };
+        match self {
+            Object::String(_) | Object::Native(_) => {}
+            Object::Function(function) => function.chunk.constant_pool.iter().for_each(visit_value),
+            Object::Closure(closure) => {
+                visit_value(&Value::Obj(closure.function));
+                for upvalue in &closure.upvalues {
+                    visit_value(&Value::Obj(*upvalue));
+                }
+            }
+            Object::Upvalue(Upvalue::Closed(value)) => visit_value(value),
+            Object::Upvalue(Upvalue::Open(_)) => {}
+            Object::Class(class) => {
+                for (name, method) in &class.methods {
+                    visit_value(&Value::Obj(*name));
+                    visit_value(&Value::Obj(*method));
+                }
+            }
+            Object::Instance(instance) => {
+                visit_value(&Value::Obj(instance.class));
+                for (name, value) in &instance.fields {
+                    visit_value(&Value::Obj(*name));
+                    visit_value(value);
+                }
+            }
+            Object::BoundMethod(bound) => {
+                visit_value(&bound.receiver);
+                visit_value(&Value::Obj(bound.method));
+            }
+        }
+    }
+}
+
+/// Owns every object of a Lox program and frees the unreachable ones with a
+/// mark-and-sweep collector.
+///
+/// The heap does not know the program's roots, so collecting is up to the
+/// [`VM`](crate::vm::VM), which only does so between instructions. At that
+/// point every live value is on the stack, in a global or in a call frame.
+pub struct Heap {
+    objects: Vec<Option<Object>>,
+    marked: Vec<bool>,
+    /// Slots of collected objects, reused by later allocations
+    free: Vec<usize>,
+    /// Interned strings. Collecting a string also removes it from here
+    strings: HashMap<String, ObjRef>,
+    bytes_allocated: usize,
+    next_gc: usize,
+    /// Collect whenever possible after an allocation, to find objects that are
+    /// freed too early
+    stress: bool,
+    /// Whether anything was allocated since the last collection
+    allocated: bool,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self {
+            objects: Vec::new(),
+            marked: Vec::new(),
+            free: Vec::new(),
+            strings: HashMap::new(),
+            bytes_allocated: 0,
+            next_gc: INITIAL_GC_THRESHOLD,
+            stress: false,
+            allocated: false,
+        }
+    }
+
+    /// The number of objects currently alive on the heap.
+    pub fn len(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn set_stress(&mut self, stress: bool) {
+        self.stress = stress;
+    }
+
+    pub(crate) fn alloc(&mut self, object: Object) -> ObjRef {
+        self.bytes_allocated += object.size();
+        self.allocated = true;
+        match self.free.pop() {
+            Some(slot) => {
+                self.objects[slot] = Some(object);
+                ObjRef(slot)
+            }
+            None => {
+                self.objects.push(Some(object));
+                self.marked.push(false);
+                ObjRef(self.objects.len() - 1)
+            }
+        }
+    }
+
+    /// Returns the string object for `string`, allocating it if it is new.
+    pub(crate) fn intern(&mut self, string: &str) -> ObjRef {
+        if let Some(&obj) = self.strings.get(string) {
+            return obj;
+        }
+        let obj = self.alloc(Object::String(string.to_string()));
+        self.strings.insert(string.to_string(), obj);
+        obj
+    }
+
+    pub(crate) fn get(&self, obj: ObjRef) -> &Object {
+        self.objects[obj.0]
+            .as_ref()
+            .expect("objects are only collected once unreachable")
+    }
+
+    pub(crate) fn get_mut(&mut self, obj: ObjRef) -> &mut Object {
+        self.objects[obj.0]
+            .as_mut()
+            .expect("objects are only collected once unreachable")
+    }
+
+    pub(crate) fn string(&self, obj: ObjRef) -> &str {
+        match self.get(obj) {
+            Object::String(s) => s,
+            _ => unreachable!("expected a string"),
+        }
+    }
+
+    pub(crate) fn function(&self, obj: ObjRef) -> &Rc<Function> {
+        match self.get(obj) {
+            Object::Function(function) => function,
+            _ => unreachable!("expected a function"),
+        }
+    }
+
+    pub(crate) fn closure(&self, obj: ObjRef) -> &Closure {
+        match self.get(obj) {
+            Object::Closure(closure) => closure,
+            _ => unreachable!("expected a closure"),
+        }
+    }
+
+    pub(crate) fn upvalue(&self, obj: ObjRef) -> &Upvalue {
+        match self.get(obj) {
+            Object::Upvalue(upvalue) => upvalue,
+            _ => unreachable!("expected an upvalue"),
+        }
+    }
+
+    pub(crate) fn upvalue_mut(&mut self, obj: ObjRef) -> &mut Upvalue {
+        match self.get_mut(obj) {
+            Object::Upvalue(upvalue) => upvalue,
+            _ => unreachable!("expected an upvalue"),
+        }
+    }
+
+    pub(crate) fn class(&self, obj: ObjRef) -> &Class {
+        match self.get(obj) {
+            Object::Class(class) => class,
+            _ => unreachable!("expected a class"),
+        }
+    }
+
+    pub(crate) fn class_mut(&mut self, obj: ObjRef) -> &mut Class {
+        match self.get_mut(obj) {
+            Object::Class(class) => class,
+            _ => unreachable!("expected a class"),
+        }
+    }
+
+    /// Formats `value`, following it onto the heap if needed.
+    pub(crate) fn display(&self, value: Value) -> DisplayValue<'_> {
+        DisplayValue { heap: self, value }
+    }
+
+    pub(crate) fn to_native(&self, value: Value) -> Result<NativeValue, String> {
+        match value {
+            Value::Nil => Ok(NativeValue::Nil),
+            Value::Bool(b) => Ok(NativeValue::Bool(b)),
+            Value::Number(n) => Ok(NativeValue::Number(n)),
+            Value::Obj(obj) => match self.get(obj) {
+                Object::String(s) => Ok(NativeValue::String(s.clone())),
+                _ => Err(format!(
+                    "Can't pass {} to a native function",
+                    self.display(value)
+                )),
+            },
+        }
+    }
+
+    pub(crate) fn native_to_value(&mut self, value: NativeValue) -> Value {
+        match value {
+            NativeValue::Nil => Value::Nil,
+            NativeValue::Bool(b) => Value::Bool(b),
+            NativeValue::Number(n) => Value::Number(n),
+            NativeValue::String(s) => Value::Obj(self.intern(&s)),
+        }
+    }
+
+    pub(crate) fn should_collect(&self) -> bool {
+        (self.stress && self.allocated) || self.bytes_allocated > self.next_gc
+    }
+
+    /// Frees every object that can't be reached from `roots`.
+    pub(crate) fn collect(&mut self, roots: impl IntoIterator<Item = ObjRef>) {
+        // mark: objects whose children still have to be visited are gray
+        let mut gray = Vec::new();
+        for root in roots {
+            if !self.marked[root.0] {
+                self.marked[root.0] = true;
+                gray.push(root);
+            }
+        }
+        while let Some(obj) = gray.pop() {
+            let marked = &mut self.marked;
+            self.objects[obj.0].as_ref().unwrap().trace(|child| {
+                if !marked[child.0] {
+                    marked[child.0] = true;
+                    gray.push(child);
+                }
+            });
+        }
+
+        // sweep
+        for (slot, object) in self.objects.iter_mut().enumerate() {
+            if std::mem::take(&mut self.marked[slot]) {
+                continue;
+            }
+            if let Some(object) = object.take() {
+                self.bytes_allocated -= object.size().min(self.bytes_allocated);
+                self.free.push(slot);
+            }
+        }
+        let objects = &self.objects;
+        self.strings.retain(|_, obj| objects[obj.0].is_some());
+
+        self.next_gc = (self.bytes_allocated * GC_HEAP_GROW_FACTOR).max(INITIAL_GC_THRESHOLD);
+        self.allocated = false;
+    }
+}
+
+pub(crate) struct DisplayValue<'a> {
+    heap: &'a Heap,
+    value: Value,
+}
+
+impl Display for DisplayValue<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let heap = self.heap;
+        let obj = match self.value {
+            Value::Number(n) => return write!(f, "{}", n),
+            Value::Bool(b) => return write!(f, "{}", b),
+            Value::Nil => return write!(f, "nil"),
+            Value::Obj(obj) => obj,
+        };
+        match heap.get(obj) {
+            Object::String(s) => write!(f, "{}", s),
+            Object::Function(function) => write!(f, "{}", function),
+            Object::Native(native) => write!(f, "{}", native),
+            Object::Closure(closure) => write!(f, "{}", heap.function(closure.function)),
+            Object::Upvalue(_) => write!(f, "upvalue"),
+            Object::Class(class) => write!(f, "<class {}>", class.name),
+            Object::Instance(instance) => {
+                write!(f, "<instance {}>", heap.class(instance.class).name)
+            }
+            Object::BoundMethod(bound) => {
+                let method = heap.closure(bound.method);
+                write!(f, "{}", heap.function(method.function))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn closed(heap: &mut Heap, value: Value) -> ObjRef {
+        heap.alloc(Object::Upvalue(Upvalue::Closed(value)))
+    }
+
+    #[test]
+    fn frees_what_roots_dont_reach() {
+        let mut heap = Heap::new();
+        let kept = heap.intern("kept");
+        let dropped = heap.intern("dropped");
+        heap.collect([kept]);
+
+        assert_eq!(heap.len(), 1);
+        assert_eq!(heap.string(kept), "kept");
+        assert!(heap.objects[dropped.0].is_none());
+    }
+
+    #[test]
+    fn keeps_objects_reachable_through_others() {
+        let mut heap = Heap::new();
+        let string = heap.intern("inner");
+        let inner = closed(&mut heap, Value::Obj(string));
+        let outer = closed(&mut heap, Value::Obj(inner));
+        heap.collect([outer]);
+
+        assert_eq!(heap.len(), 3);
+        assert_eq!(heap.string(string), "inner");
+    }
+
+    #[test]
+    fn frees_unreachable_cycles() {
+        let mut heap = Heap::new();
+        let first = closed(&mut heap, Value::Nil);
+        let second = closed(&mut heap, Value::Obj(first));
+        *heap.upvalue_mut(first) = Upvalue::Closed(Value::Obj(second));
+
+        heap.collect([first]);
+        assert_eq!(heap.len(), 2);
+        heap.collect([]);
+        assert!(heap.is_empty());
+    }
+
+    #[test]
+    fn forgets_collected_strings() {
+        let mut heap = Heap::new();
+        let old = heap.intern("name");
+        heap.collect([]);
+        assert!(heap.strings.is_empty());
+
+        // the freed slot is reused for the new string
+        let new = heap.intern("name");
+        assert_eq!(new, old);
+        assert_eq!(heap.string(new), "name");
+        assert_eq!(heap.len(), 1);
+    }
+
+    #[test]
+    fn unmarks_survivors() {
+        let mut heap = Heap::new();
+        let string = heap.intern("survivor");
+        heap.collect([string]);
+        assert!(!heap.marked[string.0]);
+
+        // marks left over from the last collection would keep it alive
+        heap.collect([]);
+        assert!(heap.is_empty());
+    }
+
+    #[test]
+    fn stress_collects_after_allocations() {
+        let mut heap = Heap::new();
+        heap.set_stress(true);
+        assert!(!heap.should_collect());
+        heap.intern("new");
+        assert!(heap.should_collect());
+        heap.collect([]);
+        assert!(!heap.should_collect());
+    }
+}
@@ -1,5 +1,3 @@
-use ustr::Ustr;
-
 use super::values::Value;
 
 /// high level instructions (eg. no constantLong)
@@ -9,11 +7,12 @@ pub(crate) enum Instruction {
     Return,
     Print,
     Pop,
-    DefineGlobal(Ustr),
-    GetGlobal(Ustr),
-    SetGlobal(Ustr),
-    SetLocal(u8),
-    GetLocal(u8),
+    /// Global variables are referred to by their slot in the global table
+    DefineGlobal(u16),
+    GetGlobal(u16),
+    SetGlobal(u16),
+    SetLocal(u16),
+    GetLocal(u16),
     Not,
     Negate,
     Equal,
@@ -23,7 +22,30 @@ pub(crate) enum Instruction {
     Subtract,
     Multiply,
     Divide,
+    /// Forward jumps are emitted long, as their distance isn't known yet
     Jump(i32),
     JumpIfFalse(i32),
+    /// Jumps back to the given offset
     Loop(usize),
+    /// Calls the value below the given number of arguments on the stack
+    Call(u8),
+    /// Wraps the function in a closure. For every variable it captures, whether
+    /// that is a local of the enclosing function and its index there
+    Closure(Value, Vec<(bool, u16)>),
+    GetUpvalue(u8),
+    SetUpvalue(u8),
+    /// Moves the local on top of the stack into the closures capturing it
+    CloseUpvalue,
+    Class(String),
+    /// Copies the methods of the superclass below the class on top of the stack
+    Inherit,
+    /// Adds the closure on top of the stack as a method to the class below it
+    Method(String),
+    GetProperty(String),
+    SetProperty(String),
+    /// Calls a method of the receiver below the given number of arguments
+    Invoke(String, u8),
+    /// Binds the superclass method to `this`, with the superclass on top of the stack
+    GetSuper(String),
+    SuperInvoke(String, u8),
 }
@@ -0,0 +1,345 @@
+//! The `.loxc` file format, which stores a compiled [`Program`] so it can be
+//! run without compiling it again.
+//!
+//! A file starts with a header:
+//!
+//! | bytes | contents                                    |
+//! |-------|---------------------------------------------|
+//! | 4     | the magic bytes `RLXC`                      |
+//! | 2     | the format version                          |
+//! | 4     | the CRC-32 checksum of everything after it  |
+//!
+//! followed by the names of the globals and the top-level chunk. Numbers are
+//! little endian, lengths and counts are `u32`s and strings are their length
+//! followed by their UTF-8 bytes. A chunk is its code, its constants and the
+//! source ranges of the code, each stored with the offset it starts at. Every
+//! constant starts with a tag byte, functions contain their own chunk.
+
+use std::{fmt::Display, rc::Rc};
+
+use super::{
+    verifier::MAX_FUNCTION_NESTING, Chunk, Function, GlobalTable, Heap, Object, Program, Value,
+    VerifyError,
+};
+use crate::interpreter::SourceCodeRange;
+
+const MAGIC: &[u8; 4] = b"RLXC";
+/// Bumped whenever the layout or the instruction set changes, old files have
+/// to be built again
+const FORMAT_VERSION: u16 = 4;
+const HEADER_LEN: usize = MAGIC.len() + 2 + 4;
+
+const TAG_NIL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_NUMBER: u8 = 3;
+const TAG_STRING: u8 = 4;
+const TAG_FUNCTION: u8 = 5;
+
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file doesn't start with the magic bytes, so it isn't a `.loxc` file
+    NotBytecode,
+    UnsupportedVersion(u16),
+    ChecksumMismatch,
+    /// The file ends in the middle of something
+    Truncated,
+    InvalidString,
+    InvalidConstantTag(u8),
+    /// Functions are nested in each other deeper than the verifier allows
+    NestedTooDeep,
+    /// There is data after the top-level chunk
+    TrailingData,
+    /// The file is well-formed, but its bytecode is not safe to run
+    Invalid(VerifyError),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::NotBytecode => write!(f, "Not a compiled lox file"),
+            LoadError::UnsupportedVersion(version) => write!(
+                f,
+                "Bytecode format version {} is not supported, expected version {}. Build the file again",
+                version, FORMAT_VERSION
+            ),
+            LoadError::ChecksumMismatch => write!(f, "Checksum mismatch, the file is corrupted"),
+            LoadError::Truncated => write!(f, "Unexpected end of file"),
+            LoadError::InvalidString => write!(f, "Invalid UTF-8 in a string"),
+            LoadError::InvalidConstantTag(tag) => write!(f, "Invalid constant tag {}", tag),
+            LoadError::NestedTooDeep => write!(f, "Functions are nested too deeply"),
+            LoadError::TrailingData => write!(f, "Unexpected data after the program"),
+            LoadError::Invalid(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Program {
+    /// Serializes the program into the `.loxc` format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        write_u32(&mut payload, self.globals.len());
+        for slot in 0..self.globals.len() {
+            write_str(&mut payload, self.globals.name(slot as u16));
+        }
+        write_chunk(&mut payload, &self.chunk, &self.heap);
+
+        let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&crc32(&payload).to_le_bytes());
+        bytes.extend_from_slice(&payload);
+        bytes
+    }
+
+    /// Loads a program serialized by [`Program::to_bytes`], and verifies it as
+    /// the file may not have been written by the compiler.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Program, LoadError> {
+        if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(LoadError::NotBytecode);
+        }
+        if bytes.len() < HEADER_LEN {
+            return Err(LoadError::Truncated);
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != FORMAT_VERSION {
+            return Err(LoadError::UnsupportedVersion(version));
+        }
+        let checksum = u32::from_le_bytes(bytes[6..HEADER_LEN].try_into().unwrap());
+        let payload = &bytes[HEADER_LEN..];
+        if crc32(payload) != checksum {
+            return Err(LoadError::ChecksumMismatch);
+        }
+
+        let mut reader = Reader {
+            bytes: payload,
+            position: 0,
+            heap: Heap::new(),
+            depth: 0,
+        };
+        let mut globals = GlobalTable::new();
+        for _ in 0..reader.u32()? {
+            // names are unique, so every one gets the next slot
+            globals.resolve(&reader.string()?);
+        }
+        let chunk = reader.chunk()?;
+        if reader.position != payload.len() {
+            return Err(LoadError::TrailingData);
+        }
+        let program = Program {
+            chunk,
+            heap: reader.heap,
+            globals,
+        };
+        program.verify().map_err(LoadError::Invalid)?;
+        Ok(program)
+    }
+}
+
+fn write_u32(bytes: &mut Vec<u8>, n: usize) {
+    let n: u32 = n.try_into().expect("lengths fit into 32 bits");
+    bytes.extend_from_slice(&n.to_le_bytes());
+}
+
+fn write_str(bytes: &mut Vec<u8>, s: &str) {
+    write_u32(bytes, s.len());
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, chunk: &Chunk, heap: &Heap) {
+    write_u32(bytes, chunk.code_array.len());
+    bytes.extend_from_slice(&chunk.code_array);
+    write_u32(bytes, chunk.constant_pool.len());
+    for constant in &chunk.constant_pool {
+        write_value(bytes, *constant, heap);
+    }
+    write_u32(bytes, chunk.ranges.len());
+    for (start, range) in &chunk.ranges {
+        write_u32(bytes, *start);
+        write_u32(bytes, range.line);
+        write_u32(bytes, range.start_column);
+        write_u32(bytes, range.length);
+    }
+}
+
+fn write_value(bytes: &mut Vec<u8>, value: Value, heap: &Heap) {
+    match value {
+        Value::Nil => bytes.push(TAG_NIL),
+        Value::Bool(false) => bytes.push(TAG_FALSE),
+        Value::Bool(true) => bytes.push(TAG_TRUE),
+        Value::Number(n) => {
+            bytes.push(TAG_NUMBER);
+            bytes.extend_from_slice(&n.to_le_bytes());
+        }
+        Value::Obj(obj) => match heap.get(obj) {
+            Object::String(s) => {
+                bytes.push(TAG_STRING);
+                write_str(bytes, s);
+            }
+            Object::Function(function) => {
+                bytes.push(TAG_FUNCTION);
+                write_str(bytes, &function.name);
+                write_u32(bytes, function.arity);
+                write_u32(bytes, function.upvalue_count);
+                write_chunk(bytes, &function.chunk, heap);
+            }
+            _ => unreachable!("the compiler only puts strings and functions into constant pools"),
+        },
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    /// Where the strings and functions read so far are allocated
+    heap: Heap,
+    /// How many functions the constant being read is nested in
+    depth: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], LoadError> {
+        let end = self.position.checked_add(len).ok_or(LoadError::Truncated)?;
+        let bytes = self
+            .bytes
+            .get(self.position..end)
+            .ok_or(LoadError::Truncated)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<usize, LoadError> {
+        let bytes = self.take(4)?.try_into().unwrap();
+        Ok(u32::from_le_bytes(bytes) as usize)
+    }
+
+    fn string(&mut self) -> Result<String, LoadError> {
+        let len = self.u32()?;
+        let bytes = self.take(len)?.to_vec();
+        String::from_utf8(bytes).map_err(|_| LoadError::InvalidString)
+    }
+
+    fn chunk(&mut self) -> Result<Chunk, LoadError> {
+        let mut chunk = Chunk::new();
+        let code_len = self.u32()?;
+        chunk.code_array = self.take(code_len)?.to_vec();
+        for _ in 0..self.u32()? {
+            let constant = self.value()?;
+            chunk.constant_pool.push(constant);
+        }
+        for _ in 0..self.u32()? {
+            let start = self.u32()?;
+            let line = self.u32()?;
+            let start_column = self.u32()?;
+            let length = self.u32()?;
+            chunk.ranges.push((
+                start,
+                SourceCodeRange {
+                    line,
+                    start_column,
+                    length,
+                },
+            ));
+        }
+        Ok(chunk)
+    }
+
+    fn value(&mut self) -> Result<Value, LoadError> {
+        match self.u8()? {
+            TAG_NIL => Ok(Value::Nil),
+            TAG_FALSE => Ok(Value::Bool(false)),
+            TAG_TRUE => Ok(Value::Bool(true)),
+            TAG_NUMBER => {
+                let bytes = self.take(8)?.try_into().unwrap();
+                Ok(Value::Number(f64::from_le_bytes(bytes)))
+            }
+            TAG_STRING => {
+                let s = self.string()?;
+                Ok(Value::Obj(self.heap.intern(&s)))
+            }
+            TAG_FUNCTION => {
+                let name = self.string()?;
+                let arity = self.u32()?;
+                let upvalue_count = self.u32()?;
+                // functions are read recursively, like the verifier checks them
+                if self.depth == MAX_FUNCTION_NESTING {
+                    return Err(LoadError::NestedTooDeep);
+                }
+                self.depth += 1;
+                let chunk = self.chunk()?;
+                self.depth -= 1;
+                let function = Function {
+                    name,
+                    arity,
+                    upvalue_count,
+                    chunk,
+                };
+                Ok(Value::Obj(
+                    self.heap.alloc(Object::Function(Rc::new(function))),
+                ))
+            }
+            tag => Err(LoadError::InvalidConstantTag(tag)),
+        }
+    }
+}
+
+/// The CRC-32 checksum (as used by zip and PNG) of `bytes`.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A file whose top-level chunk holds a function that holds a function,
+    /// `depth` levels deep. None of the chunks has any code.
+    fn nested_functions(depth: usize) -> Vec<u8> {
+        let mut payload = Vec::new();
+        write_u32(&mut payload, 0);
+        for _ in 0..depth {
+            // the code and the constant count of the enclosing chunk
+            write_u32(&mut payload, 0);
+            write_u32(&mut payload, 1);
+            payload.push(TAG_FUNCTION);
+            write_str(&mut payload, "f");
+            write_u32(&mut payload, 0);
+            write_u32(&mut payload, 0);
+        }
+        for _ in 0..3 {
+            write_u32(&mut payload, 0);
+        }
+        // the source ranges of every enclosing chunk
+        for _ in 0..depth {
+            write_u32(&mut payload, 0);
+        }
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&crc32(&payload).to_le_bytes());
+        bytes.extend_from_slice(&payload);
+        bytes
+    }
+
+    #[test]
+    fn limits_function_nesting() {
+        // read completely, then rejected by the verifier for having no code
+        let result = Program::from_bytes(&nested_functions(MAX_FUNCTION_NESTING));
+        assert!(matches!(result, Err(LoadError::Invalid(_))));
+
+        let result = Program::from_bytes(&nested_functions(MAX_FUNCTION_NESTING + 1));
+        assert!(matches!(result, Err(LoadError::NestedTooDeep)));
+    }
+}
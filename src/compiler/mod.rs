@@ -1,182 +1,527 @@
-use std::fmt::Display;
+use std::{fmt::Display, rc::Rc};
 
+pub use globals::GlobalTable;
+pub use heap::{Heap, ObjRef, Object};
 use instructions::Instruction;
+pub use loxc::LoadError;
 pub use op_codes::OpCode;
-pub use values::Value;
+pub use values::{BoundMethod, Class, Closure, Function, Instance, Upvalue, Value};
+use verifier::MAX_FUNCTION_NESTING;
+pub use verifier::{VerifyError, VerifyErrorKind};
 
 use crate::interpreter::{
-    lexer,
+    diagnostic::Diagnostic,
+    fold, lexer,
     parser::{
         self,
         ast::{Expr, Stmt},
     },
+    resolver::{Resolver, ResolverError},
     SourceCodeRange,
 };
 
 mod chunk;
-pub(crate) mod disassembler;
+pub mod disassembler;
+mod globals;
+mod heap;
 mod instructions;
+mod loxc;
 mod op_codes;
+mod optimizer;
 mod values;
+mod verifier;
 pub use chunk::Chunk;
 
+/// The longest jump, its distance has to fit into three bytes
+const MAX_JUMP: usize = 0xff_ffff;
+/// The highest index in a constant pool, it has to fit into three bytes
+const MAX_CONSTANT: usize = 0xff_ffff;
+
 #[derive(Debug)]
 pub enum CompilerError {
-    LexError,
-    ParseError(()),
-    // ResolverError(resolver::ResolverError),
-    CompileError(CompileError),
-    // ExecError(()),
-    // ResolverError(()),
+    LexError(Vec<Diagnostic>),
+    ParseError(Vec<Diagnostic>),
+    ResolverError(ResolverError),
+    /// An error of the compiler itself, and the statement or expression it
+    /// happened in
+    CompileError(CompileError, SourceCodeRange),
 }
 
-impl Display for CompilerError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl CompilerError {
+    /// The exit status `rlox` ends with, the same the tree-walker uses for
+    /// the error: 75 for scope errors its resolver finds, 65 otherwise.
+    pub fn status(&self) -> i32 {
+        match self {
+            CompilerError::ResolverError(_) => 75,
+            CompilerError::CompileError(err, _) if err.is_scope_error() => 75,
+            _ => 65,
+        }
+    }
+
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
         match self {
-            CompilerError::LexError => write!(f, "Lex error"),
-            CompilerError::ParseError(_) => write!(f, "Parse error"),
-            CompilerError::CompileError(err) => write!(f, "Compile error: {:?}", err),
-            // CompilerError::ExecError(_) => write!(f, "Exec error"),
-            // CompilerError::ResolverError(_) => write!(f, "Resolver error"),
+            CompilerError::LexError(diagnostics) | CompilerError::ParseError(diagnostics) => {
+                diagnostics.clone()
+            }
+            CompilerError::ResolverError(err) => vec![err.into()],
+            CompilerError::CompileError(err, range) => vec![err.diagnostic(*range)],
         }
     }
 }
 
+impl Display for CompilerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let diagnostics = self
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect::<Vec<_>>();
+        write!(f, "{}", diagnostics.join("\n"))
+    }
+}
+
 #[derive(Debug)]
 pub enum CompileError {
     LiteralToValueError,
-    VariableAlreadyDefined,
-    VariableNotDefined,
+    VariableAlreadyDefined(String),
+    BreakOutsideLoop,
+    ContinueOutsideLoop,
+    ReturnOutsideFunction,
+    ReturnFromInitializer,
+    TooManyUpvalues,
+    TooManyGlobals,
+    /// A function has more locals than fit into two bytes
+    TooManyLocals,
+    /// A jump is too long for three bytes
+    JumpTooLarge,
+    /// A chunk has more constants than its instructions can refer to
+    TooManyConstants,
+    /// Functions are nested deeper than a `.loxc` file may nest them
+    NestedTooDeep,
+    ThisOutsideClass,
+    SuperOutsideClass,
+    SuperWithoutSuperclass,
+    InheritFromSelf,
 }
 
-impl From<CompileError> for CompilerError {
-    fn from(err: CompileError) -> Self {
-        CompilerError::CompileError(err)
+impl CompileError {
+    /// The diagnostic for the error at `range`. Errors the resolver finds for
+    /// the tree-walker as well share its code and message.
+    fn diagnostic(&self, range: SourceCodeRange) -> Diagnostic {
+        let code = match self {
+            CompileError::VariableAlreadyDefined(_) => "R0001",
+            CompileError::ReturnOutsideFunction => "R0002",
+            CompileError::BreakOutsideLoop => "R0003",
+            CompileError::ContinueOutsideLoop => "R0004",
+            CompileError::ThisOutsideClass => "R0005",
+            CompileError::InheritFromSelf => "R0006",
+            CompileError::SuperOutsideClass => "R0007",
+            CompileError::SuperWithoutSuperclass => "R0008",
+            CompileError::ReturnFromInitializer => "R0010",
+            CompileError::LiteralToValueError => "C0001",
+            CompileError::TooManyUpvalues => "C0002",
+            CompileError::TooManyGlobals => "C0003",
+            CompileError::TooManyLocals => "C0004",
+            CompileError::JumpTooLarge => "C0005",
+            CompileError::TooManyConstants => "C0006",
+            CompileError::NestedTooDeep => "C0007",
+        };
+        Diagnostic::error(code, self.to_string(), range)
+    }
+
+    /// Whether the error is about where a name or keyword is used, which the
+    /// tree-walker finds in its resolver instead.
+    pub fn is_scope_error(&self) -> bool {
+        !matches!(
+            self,
+            CompileError::LiteralToValueError
+                | CompileError::TooManyUpvalues
+                | CompileError::TooManyGlobals
+                | CompileError::TooManyLocals
+                | CompileError::JumpTooLarge
+                | CompileError::TooManyConstants
+                | CompileError::NestedTooDeep
+        )
     }
 }
 
-impl From<values::LiteralToValueError> for CompileError {
-    fn from(_: values::LiteralToValueError) -> Self {
-        CompileError::LiteralToValueError
+impl Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompileError::LiteralToValueError => write!(f, "Can't compile a runtime value."),
+            CompileError::VariableAlreadyDefined(name) => {
+                write!(f, "Already a variable named '{}' in this scope.", name)
+            }
+            CompileError::BreakOutsideLoop => write!(f, "Can't use 'break' outside of a loop."),
+            CompileError::ContinueOutsideLoop => {
+                write!(f, "Can't use 'continue' outside of a loop.")
+            }
+            CompileError::ReturnOutsideFunction => write!(f, "Can't return from top-level code."),
+            CompileError::ReturnFromInitializer => {
+                write!(f, "Can't return a value from an initializer.")
+            }
+            CompileError::TooManyUpvalues => {
+                write!(
+                    f,
+                    "Too many closure variables in function, the limit is 256."
+                )
+            }
+            CompileError::TooManyGlobals => {
+                write!(f, "Too many global variables, the limit is 65536.")
+            }
+            CompileError::TooManyLocals => {
+                write!(
+                    f,
+                    "Too many local variables in function, the limit is 65536."
+                )
+            }
+            CompileError::JumpTooLarge => {
+                write!(f, "Too much code to jump over, the limit is 16 MiB.")
+            }
+            CompileError::TooManyConstants => {
+                write!(f, "Too many constants in one chunk, the limit is 16777216.")
+            }
+            CompileError::NestedTooDeep => write!(
+                f,
+                "Functions are nested too deeply, the limit is {}.",
+                MAX_FUNCTION_NESTING
+            ),
+            CompileError::ThisOutsideClass => write!(f, "Can't use 'this' outside of a class."),
+            CompileError::SuperOutsideClass => write!(f, "Can't use 'super' outside of a class."),
+            CompileError::SuperWithoutSuperclass => {
+                write!(f, "Can't use 'super' in a class with no superclass.")
+            }
+            CompileError::InheritFromSelf => write!(f, "A class can't inherit from itself."),
+        }
     }
 }
 
 struct Local {
     name: String,
     depth: i32,
+    /// Whether a closure captures the local, so it has to be closed over
+    /// instead of popped when it goes out of scope
+    is_captured: bool,
+}
+
+/// A loop whose body is being compiled.
+struct Loop {
+    /// Locals declared deeper than this are popped when jumping out of the body
+    scope_depth: i32,
+    /// Jumps emitted by `break`, patched to the end of the loop
+    break_jumps: Vec<usize>,
+    /// Jumps emitted by `continue`, patched to the increment or condition
+    continue_jumps: Vec<usize>,
+}
+
+/// The result of compiling a program: its top-level code, the heap holding
+/// the strings and functions it refers to and its global variables.
+pub struct Program {
+    pub(crate) chunk: Chunk,
+    pub(crate) heap: Heap,
+    pub(crate) globals: GlobalTable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    /// An `init` method, which always returns `this`
+    Initializer,
 }
 
 pub struct Compiler {
+    /// The compiler of the surrounding function while a nested one is compiled
+    enclosing: Option<Box<Compiler>>,
+    /// Name of the function being compiled, empty for the top-level script
+    function_name: String,
+    kind: FunctionKind,
+    /// For every class whose body is being compiled, innermost last, whether
+    /// it has a superclass
+    classes: Vec<bool>,
+    /// Where strings and functions are allocated. Handed on to the nested
+    /// compiler while a function is compiled
+    heap: Heap,
+    /// Shared with nested compilers the same way as the heap
+    globals: GlobalTable,
     locals: Vec<Local>,
+    /// Variables of enclosing functions captured by this function, as whether
+    /// they are a local of the directly enclosing function and their index
+    upvalues: Vec<(bool, u16)>,
     scope_depth: i32,
+    loops: Vec<Loop>,
     chunk: Chunk,
     current_range: SourceCodeRange,
 }
 
 impl Compiler {
     pub fn new() -> Self {
+        Self::for_function(String::new(), FunctionKind::Script)
+    }
+
+    fn for_function(function_name: String, kind: FunctionKind) -> Self {
+        // slot 0 holds the function being called, or the receiver of a method
+        let slot_zero = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => "",
+        };
         Self {
-            locals: Vec::new(),
+            enclosing: None,
+            function_name,
+            kind,
+            classes: Vec::new(),
+            heap: Heap::new(),
+            globals: GlobalTable::new(),
+            locals: vec![Local {
+                name: slot_zero.to_string(),
+                depth: 0,
+                is_captured: false,
+            }],
+            upvalues: Vec::new(),
             scope_depth: 0,
+            loops: Vec::new(),
             chunk: Chunk::new(),
             current_range: SourceCodeRange::new(0),
         }
     }
 
     pub fn compile(&mut self, input: &str) -> Result<(), CompilerError> {
-        let tokens = lexer::tokenize(input).map_err(|_| CompilerError::LexError)?;
+        let (tokens, lex_errors) = lexer::tokenize(input);
 
         let mut parser = parser::ParserInstance::new(tokens);
-        let stmnts = parser.parse().map_err(|_| CompilerError::ParseError(()))?;
+        let parsed = parser.parse();
+        if !lex_errors.is_empty() {
+            let mut diagnostics = lex_errors;
+            if let Err(parse_errors) = parsed {
+                diagnostics.extend(parse_errors);
+            }
+            return Err(CompilerError::LexError(diagnostics));
+        }
+        let stmnts = parsed.map_err(CompilerError::ParseError)?;
+        // the tree-walker's checks, run before folding so that code which can
+        // never run is still checked
+        Resolver::new()
+            .resolve(&stmnts)
+            .map_err(CompilerError::ResolverError)?;
+        let stmnts = fold::fold(stmnts);
 
         for stmt in &stmnts {
             self.current_range = stmt.range;
-            stmt.compile(self)?;
+            stmt.compile(self)
+                .map_err(|err| CompilerError::CompileError(err, self.current_range))?;
         }
-        self.add_instruction(Instruction::Return, SourceCodeRange::new(2));
-        Ok(())
+        self.emit_return(self.current_range)
+            .map_err(|err| CompilerError::CompileError(err, self.current_range))
     }
 
-    pub fn into_chunk(self) -> Chunk {
-        self.chunk
+    pub fn into_program(self) -> Program {
+        Program {
+            chunk: optimizer::narrow_jumps(self.chunk, &self.heap),
+            heap: self.heap,
+            globals: self.globals,
+        }
+    }
+
+    fn function_constant(&mut self, function: Function) -> Value {
+        Value::Obj(self.heap.alloc(Object::Function(Rc::new(function))))
     }
 
-    pub fn add_instruction(&mut self, instruction: Instruction, range: SourceCodeRange) {
+    fn literal_value(&mut self, literal: &parser::ast::Literal) -> Result<Value, CompileError> {
+        use parser::ast::Literal;
+        match literal {
+            Literal::Number(n) => Ok(Value::Number(*n)),
+            Literal::True => Ok(Value::Bool(true)),
+            Literal::False => Ok(Value::Bool(false)),
+            Literal::Nil => Ok(Value::Nil),
+            Literal::String(s) => Ok(Value::Obj(self.heap.intern(s))),
+            _ => Err(CompileError::LiteralToValueError),
+        }
+    }
+
+    /// Returns `nil` if the end of a function is reached without a `return`,
+    /// or `this` from an initializer.
+    fn emit_return(&mut self, range: SourceCodeRange) -> Result<(), CompileError> {
+        if self.kind == FunctionKind::Initializer {
+            self.add_instruction(Instruction::GetLocal(0), range)?;
+        } else {
+            self.add_instruction(Instruction::Constant(Value::Nil), range)?;
+        }
+        self.add_instruction(Instruction::Return, range)
+    }
+
+    /// Compiles a function body into its own chunk, with the parameters as
+    /// its first locals.
+    fn compile_function(
+        &mut self,
+        name: &str,
+        kind: FunctionKind,
+        params: &[String],
+        body: &Stmt,
+    ) -> Result<(Function, Vec<(bool, u16)>), CompileError> {
+        let mut depth = 1;
+        let mut compiler = &*self;
+        while let Some(enclosing) = &compiler.enclosing {
+            depth += 1;
+            compiler = enclosing;
+        }
+        if depth > MAX_FUNCTION_NESTING {
+            return Err(CompileError::NestedTooDeep);
+        }
+
+        let mut enclosing = std::mem::replace(self, Compiler::for_function(name.to_string(), kind));
+        // the classes being compiled stay visible to methods and their closures
+        self.classes = std::mem::take(&mut enclosing.classes);
+        self.heap = std::mem::take(&mut enclosing.heap);
+        self.globals = std::mem::take(&mut enclosing.globals);
+        self.enclosing = Some(Box::new(enclosing));
+        self.current_range = body.range;
+
+        self.begin_scope();
+        let compiled = params
+            .iter()
+            .try_for_each(|param| self.add_local(param.clone()))
+            .and_then(|()| match &body.intern {
+                // the parameters and the body share a scope
+                parser::ast::StmtType::Block(stmts) => {
+                    stmts.iter().try_for_each(|stmt| stmt.compile(self))
+                }
+                _ => body.compile(self),
+            })
+            .and_then(|()| self.emit_return(self.current_range));
+
+        let enclosing = self.enclosing.take().unwrap();
+        let mut function = std::mem::replace(self, *enclosing);
+        self.classes = std::mem::take(&mut function.classes);
+        self.heap = std::mem::take(&mut function.heap);
+        self.globals = std::mem::take(&mut function.globals);
+        if compiled.is_err() {
+            // where in the body the error happened
+            self.current_range = function.current_range;
+        }
+        compiled?;
+        Ok((
+            Function {
+                name: function.function_name,
+                arity: params.len(),
+                upvalue_count: function.upvalues.len(),
+                chunk: optimizer::narrow_jumps(function.chunk, &self.heap),
+            },
+            function.upvalues,
+        ))
+    }
+
+    pub(crate) fn add_instruction(
+        &mut self,
+        instruction: Instruction,
+        range: SourceCodeRange,
+    ) -> Result<(), CompileError> {
         if let Ok(op) = OpCode::try_from(&instruction) {
             self.chunk.push_code(op as u8, range);
         } else {
             use Instruction::*;
             match instruction {
-                Constant(value) => {
-                    let idx = self.chunk.constant_pool.len();
-                    self.chunk.constant_pool.push(value);
-
-                    if idx > 255 {
-                        self.chunk.push_code(OpCode::OpConstantLong as u8, range);
-                        self.chunk.push_code((idx >> 16) as u8, range);
-                        self.chunk.push_code((idx >> 8) as u8, range);
-                        self.chunk.push_code(idx as u8, range);
-                        return;
+                Constant(value) => self.push_constant_op(OpCode::OpConstant, value, range)?,
+                DefineGlobal(slot) | GetGlobal(slot) | SetGlobal(slot) => {
+                    let op = match instruction {
+                        DefineGlobal(_) => OpCode::OpDefineGlobal,
+                        GetGlobal(_) => OpCode::OpGetGlobal,
+                        _ => OpCode::OpSetGlobal,
+                    };
+                    self.chunk.push_code(op as u8, range);
+                    self.chunk.push_code((slot >> 8) as u8, range);
+                    self.chunk.push_code(slot as u8, range);
+                }
+                GetLocal(idx) | SetLocal(idx) => {
+                    let get = matches!(instruction, GetLocal(_));
+                    if let Ok(idx) = u8::try_from(idx) {
+                        let op = if get {
+                            OpCode::OpGetLocal
+                        } else {
+                            OpCode::OpSetLocal
+                        };
+                        self.chunk.push_code(op as u8, range);
+                        self.chunk.push_code(idx, range);
                     } else {
-                        self.chunk.push_code(OpCode::OpConstant as u8, range);
+                        let op = if get {
+                            OpCode::OpGetLocalLong
+                        } else {
+                            OpCode::OpSetLocalLong
+                        };
+                        self.chunk.push_code(op as u8, range);
+                        self.chunk.push_code((idx >> 8) as u8, range);
                         self.chunk.push_code(idx as u8, range);
                     }
                 }
-                DefineGlobal(u) => {
-                    self.chunk.push_code(OpCode::OpDefineGlobal as u8, range);
-                    let pointer_address = u.as_ptr() as usize;
-                    // push all the bytes of the pointer address
-                    for i in 0..std::mem::size_of::<usize>() {
-                        self.chunk
-                            .push_code((pointer_address >> (i * 8)) as u8, range);
-                    }
+                Jump(idx) | JumpIfFalse(idx) => {
+                    // narrowed once the chunk is complete, if the distance fits
+                    let op = match instruction {
+                        Jump(_) => OpCode::OpJumpLong,
+                        _ => OpCode::OpJumpIfFalseLong,
+                    };
+                    self.chunk.push_code(op as u8, range);
+                    self.chunk.push_code((idx >> 16) as u8, range);
+                    self.chunk.push_code((idx >> 8) as u8, range);
+                    self.chunk.push_code(idx as u8, range);
                 }
-                GetGlobal(u) => {
-                    self.chunk.push_code(OpCode::OpGetGlobal as u8, range);
-                    let pointer_address = u.as_ptr() as usize;
-                    // push all the bytes of the pointer address
-                    for i in 0..std::mem::size_of::<usize>() {
-                        self.chunk
-                            .push_code((pointer_address >> (i * 8)) as u8, range);
-                    }
+                Call(arg_count) => {
+                    self.chunk.push_code(OpCode::OpCall as u8, range);
+                    self.chunk.push_code(arg_count, range);
                 }
-                SetGlobal(u) => {
-                    self.chunk.push_code(OpCode::OpSetGlobal as u8, range);
-                    let pointer_address = u.as_ptr() as usize;
-                    // push all the bytes of the pointer address
-                    for i in 0..std::mem::size_of::<usize>() {
-                        self.chunk
-                            .push_code((pointer_address >> (i * 8)) as u8, range);
+                Closure(function, upvalues) => {
+                    self.push_constant_op(OpCode::OpClosure, function, range)?;
+                    for (is_local, index) in upvalues {
+                        self.chunk.push_code(is_local as u8, range);
+                        self.chunk.push_code((index >> 8) as u8, range);
+                        self.chunk.push_code(index as u8, range);
                     }
                 }
-                GetLocal(idx) => {
-                    self.chunk.push_code(OpCode::OpGetLocal as u8, range);
+                GetUpvalue(idx) => {
+                    self.chunk.push_code(OpCode::OpGetUpvalue as u8, range);
                     self.chunk.push_code(idx, range);
                 }
-                SetLocal(idx) => {
-                    self.chunk.push_code(OpCode::OpSetLocal as u8, range);
+                SetUpvalue(idx) => {
+                    self.chunk.push_code(OpCode::OpSetUpvalue as u8, range);
                     self.chunk.push_code(idx, range);
                 }
-                Jump(idx) => {
-                    self.chunk.push_code(OpCode::OpJump as u8, range);
-                    self.chunk.push_code((idx >> 8) as u8, range);
-                    self.chunk.push_code(idx as u8, range);
+                Class(ref name)
+                | Method(ref name)
+                | GetProperty(ref name)
+                | SetProperty(ref name)
+                | GetSuper(ref name) => {
+                    let op = match instruction {
+                        Class(_) => OpCode::OpClass,
+                        Method(_) => OpCode::OpMethod,
+                        GetProperty(_) => OpCode::OpGetProperty,
+                        SetProperty(_) => OpCode::OpSetProperty,
+                        _ => OpCode::OpGetSuper,
+                    };
+                    let name = Value::Obj(self.heap.intern(name));
+                    self.push_constant_op(op, name, range)?;
                 }
-                JumpIfFalse(idx) => {
-                    self.chunk.push_code(OpCode::OpJumpIfFalse as u8, range);
-                    self.chunk.push_code((idx >> 8) as u8, range);
-                    self.chunk.push_code(idx as u8, range);
+                Invoke(ref name, arg_count) | SuperInvoke(ref name, arg_count) => {
+                    let op = match instruction {
+                        Invoke(..) => OpCode::OpInvoke,
+                        _ => OpCode::OpSuperInvoke,
+                    };
+                    let name = Value::Obj(self.heap.intern(name));
+                    self.push_constant_op(op, name, range)?;
+                    self.chunk.push_code(arg_count, range);
                 }
                 Loop(loop_start) => {
-                    self.chunk.push_code(OpCode::OpLoop as u8, range);
-
-                    let offset = self.chunk.code_array.len() - loop_start + 2;
-                    if offset > 0xffff {
-                        panic!("Loop body too large");
+                    // the distance is counted from after the operand
+                    let offset = self.chunk.code_array.len() + 3 - loop_start;
+                    if offset <= u16::MAX as usize {
+                        self.chunk.push_code(OpCode::OpLoop as u8, range);
+                        self.chunk.push_code((offset >> 8) as u8, range);
+                        self.chunk.push_code(offset as u8, range);
+                    } else {
+                        let offset = offset + 1;
+                        self.chunk.push_code(OpCode::OpLoopLong as u8, range);
+                        self.chunk.push_code((offset >> 16) as u8, range);
+                        self.chunk.push_code((offset >> 8) as u8, range);
+                        self.chunk.push_code(offset as u8, range);
                     }
-
-                    self.chunk.push_code((offset >> 8) as u8, range);
-                    self.chunk.push_code(offset as u8, range);
                 }
                 instr => {
                     unreachable!(
@@ -186,57 +531,338 @@ impl Compiler {
                 }
             }
         }
+        Ok(())
+    }
+
+    /// Emits `op` referring to the constant `value`, in its long form if the
+    /// index of the constant doesn't fit into a single byte.
+    fn push_constant_op(
+        &mut self,
+        op: OpCode,
+        value: Value,
+        range: SourceCodeRange,
+    ) -> Result<(), CompileError> {
+        let idx = match self.short_constant_idx(&value) {
+            Some(idx) => idx as usize,
+            None => {
+                let idx = self.chunk.constant_pool.len();
+                if idx > MAX_CONSTANT {
+                    return Err(CompileError::TooManyConstants);
+                }
+                self.chunk.constant_pool.push(value);
+                idx
+            }
+        };
+        if let Ok(idx) = u8::try_from(idx) {
+            self.chunk.push_code(op as u8, range);
+            self.chunk.push_code(idx, range);
+        } else {
+            self.chunk.push_code(op.long() as u8, range);
+            self.chunk.push_code((idx >> 16) as u8, range);
+            self.chunk.push_code((idx >> 8) as u8, range);
+            self.chunk.push_code(idx as u8, range);
+        }
+        Ok(())
+    }
+
+    /// The index of a constant equal to `value` that a single byte can refer
+    /// to, so repeated names and numbers don't use up those slots. Numbers
+    /// have to be the same bits, as `0` and `-0` are equal but print
+    /// differently.
+    fn short_constant_idx(&self, value: &Value) -> Option<u8> {
+        self.chunk
+            .constant_pool
+            .iter()
+            .take(256)
+            .position(|constant| match (constant, value) {
+                (Value::Number(a), Value::Number(b)) => a.to_bits() == b.to_bits(),
+                _ => constant == value,
+            })
+            .map(|idx| idx as u8)
     }
 
     fn begin_scope(&mut self) {
         self.scope_depth += 1;
     }
 
-    fn end_scope(&mut self) {
+    fn end_scope(&mut self, range: SourceCodeRange) -> Result<(), CompileError> {
         self.scope_depth -= 1;
 
         while let Some(local) = self.locals.last() {
             if local.depth > self.scope_depth {
-                self.locals.pop();
-                self.add_instruction(Instruction::Pop, self.current_range);
+                let local = self.locals.pop().unwrap();
+                self.add_instruction(Self::discard_local(&local), range)?;
             } else {
                 break;
             }
         }
+        Ok(())
     }
 
-    fn add_local(&mut self, name: String) {
+    /// The instruction removing `local` from the stack when it goes out of scope.
+    fn discard_local(local: &Local) -> Instruction {
+        if local.is_captured {
+            Instruction::CloseUpvalue
+        } else {
+            Instruction::Pop
+        }
+    }
+
+    fn add_local(&mut self, name: String) -> Result<(), CompileError> {
+        // slots are at most two bytes long
+        if self.locals.len() > u16::MAX as usize {
+            return Err(CompileError::TooManyLocals);
+        }
         self.locals.push(Local {
             name,
             depth: self.scope_depth,
+            is_captured: false,
         });
+        Ok(())
+    }
+
+    /// Pushes the value of the local, upvalue or global `name`.
+    fn get_variable(&mut self, name: &str, range: SourceCodeRange) -> Result<(), CompileError> {
+        if let Some(idx) = self.resolve_local(name) {
+            self.add_instruction(Instruction::GetLocal(idx), range)?;
+        } else if let Some(idx) = self.resolve_upvalue(name)? {
+            self.add_instruction(Instruction::GetUpvalue(idx), range)?;
+        } else {
+            let slot = self.global_slot(name)?;
+            self.add_instruction(Instruction::GetGlobal(slot), range)?;
+        }
+        Ok(())
+    }
+
+    /// Declares a variable whose value is on top of the stack.
+    fn define_variable(&mut self, name: &str, range: SourceCodeRange) -> Result<(), CompileError> {
+        if self.scope_depth > 0 {
+            self.add_local(name.to_string())?;
+        } else {
+            let slot = self.global_slot(name)?;
+            self.add_instruction(Instruction::DefineGlobal(slot), range)?;
+        }
+        Ok(())
+    }
+
+    fn global_slot(&mut self, name: &str) -> Result<u16, CompileError> {
+        self.globals
+            .resolve(name)
+            .ok_or(CompileError::TooManyGlobals)
+    }
+
+    /// Compiles a class declaration. Methods are added to the class one by
+    /// one, and a superclass is bound to a `super` local the methods capture.
+    fn compile_class(
+        &mut self,
+        name: &str,
+        superclass: Option<&Expr>,
+        methods: &[Stmt],
+        range: SourceCodeRange,
+    ) -> Result<(), CompileError> {
+        self.add_instruction(Instruction::Class(name.to_string()), range)?;
+        self.define_variable(name, range)?;
+
+        if let Some(superclass) = superclass {
+            if matches!(&*superclass.intern, parser::ast::ExprType::Variable(s) if s == name) {
+                return Err(CompileError::InheritFromSelf);
+            }
+            superclass.compile(self)?;
+            self.begin_scope();
+            self.add_local("super".to_string())?;
+            self.get_variable(name, range)?;
+            self.add_instruction(Instruction::Inherit, range)?;
+        }
+
+        self.classes.push(superclass.is_some());
+        self.get_variable(name, range)?;
+        let compiled = methods
+            .iter()
+            .try_for_each(|method| -> Result<(), CompileError> {
+                let parser::ast::StmtType::Function(_, method_name, params, body) = &method.intern
+                else {
+                    return Ok(());
+                };
+                let kind = if method_name == "init" {
+                    FunctionKind::Initializer
+                } else {
+                    FunctionKind::Method
+                };
+                let (function, upvalues) =
+                    self.compile_function(method_name, kind, params, body)?;
+                let function = self.function_constant(function);
+                self.add_instruction(Instruction::Closure(function, upvalues), method.range)?;
+                self.add_instruction(Instruction::Method(method_name.clone()), method.range)?;
+                Ok(())
+            });
+        self.classes.pop();
+        compiled?;
+        self.add_instruction(Instruction::Pop, range)?;
+
+        if superclass.is_some() {
+            self.end_scope(range)?;
+        }
+        Ok(())
     }
 
-    fn resolve_local(&mut self, name: &str) -> Result<usize, CompileError> {
+    /// Pushes the superclass for a `super` expression.
+    fn get_super(&mut self, range: SourceCodeRange) -> Result<(), CompileError> {
+        match self.classes.last() {
+            None => Err(CompileError::SuperOutsideClass),
+            Some(false) => Err(CompileError::SuperWithoutSuperclass),
+            Some(true) => self.get_variable("super", range),
+        }
+    }
+
+    fn resolve_local(&mut self, name: &str) -> Option<u16> {
         for (i, local) in self.locals.iter().enumerate().rev() {
             if local.name == name {
-                return Ok(i);
+                // `add_local` keeps the number of locals within two bytes
+                return Some(i as u16);
+            }
+        }
+        None
+    }
+
+    /// Looks `name` up in the locals of the enclosing functions, capturing it
+    /// in every function in between. Returns the index of the upvalue, or
+    /// `None` if `name` has to be a global.
+    fn resolve_upvalue(&mut self, name: &str) -> Result<Option<u8>, CompileError> {
+        let Some(enclosing) = self.enclosing.as_mut() else {
+            return Ok(None);
+        };
+        if let Some(idx) = enclosing.resolve_local(name) {
+            enclosing.locals[idx as usize].is_captured = true;
+            return self.add_upvalue(true, idx).map(Some);
+        }
+        match enclosing.resolve_upvalue(name)? {
+            Some(idx) => self.add_upvalue(false, idx as u16).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Captures the local or upvalue `idx` of the enclosing function. A
+    /// function can capture up to 256 variables, from any of its slots.
+    fn add_upvalue(&mut self, is_local: bool, idx: u16) -> Result<u8, CompileError> {
+        if let Some(existing) = self.upvalues.iter().position(|&u| u == (is_local, idx)) {
+            return Ok(existing as u8);
+        }
+        if self.upvalues.len() > u8::MAX as usize {
+            return Err(CompileError::TooManyUpvalues);
+        }
+        self.upvalues.push((is_local, idx));
+        Ok((self.upvalues.len() - 1) as u8)
+    }
+
+    /// Emits a jump out of the innermost loop's body for `break` or `continue`,
+    /// popping the locals of the scopes that are left.
+    fn emit_loop_exit(
+        &mut self,
+        is_break: bool,
+        range: SourceCodeRange,
+    ) -> Result<(), CompileError> {
+        let Some(scope_depth) = self.loops.last().map(|l| l.scope_depth) else {
+            return Err(if is_break {
+                CompileError::BreakOutsideLoop
+            } else {
+                CompileError::ContinueOutsideLoop
+            });
+        };
+        let discards = self
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > scope_depth)
+            .map(Self::discard_local)
+            .collect::<Vec<_>>();
+        for discard in discards {
+            self.add_instruction(discard, range)?;
+        }
+        let jump = self.emit_jump(Instruction::Jump(0), range)?;
+        let current = self.loops.last_mut().unwrap();
+        if is_break {
+            current.break_jumps.push(jump);
+        } else {
+            current.continue_jumps.push(jump);
+        }
+        Ok(())
+    }
+
+    /// Compiles a loop. `continue` jumps to the increment, or straight to the
+    /// condition if there is none.
+    fn compile_loop(
+        &mut self,
+        cond: Option<&Expr>,
+        increment: Option<&Expr>,
+        body: &Stmt,
+        range: SourceCodeRange,
+    ) -> Result<(), CompileError> {
+        let loop_start = self.chunk.code_array.len();
+        let exit_jump = match cond {
+            Some(cond) => {
+                cond.compile(self)?;
+                let exit_jump = self.emit_jump(Instruction::JumpIfFalse(0), range)?;
+                self.add_instruction(Instruction::Pop, range)?;
+                Some(exit_jump)
             }
+            None => None,
+        };
+
+        self.loops.push(Loop {
+            scope_depth: self.scope_depth,
+            break_jumps: Vec::new(),
+            continue_jumps: Vec::new(),
+        });
+        let compiled = body.compile(self);
+        let current = self.loops.pop().unwrap();
+        compiled?;
+
+        for jump in current.continue_jumps {
+            self.patch_jump(jump)?;
+        }
+        if let Some(increment) = increment {
+            increment.compile(self)?;
+            self.add_instruction(Instruction::Pop, range)?;
+        }
+        // a long loop instruction is four bytes
+        if self.chunk.code_array.len() + 4 - loop_start > MAX_JUMP {
+            return Err(CompileError::JumpTooLarge);
+        }
+        self.add_instruction(Instruction::Loop(loop_start), range)?;
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump)?;
+            self.add_instruction(Instruction::Pop, range)?;
+        }
+        for jump in current.break_jumps {
+            self.patch_jump(jump)?;
         }
-        Err(CompileError::VariableNotDefined)
+        Ok(())
     }
 
-    fn emit_jump(&mut self, instruction: Instruction) -> usize {
+    fn emit_jump(
+        &mut self,
+        instruction: Instruction,
+        range: SourceCodeRange,
+    ) -> Result<usize, CompileError> {
         assert!(matches!(
             instruction,
             Instruction::Jump(_) | Instruction::JumpIfFalse(_)
         ));
-        self.add_instruction(instruction, self.current_range);
-        self.chunk.code_array.len() - 2
+        self.add_instruction(instruction, range)?;
+        Ok(self.chunk.code_array.len() - 3)
     }
 
-    fn patch_jump(&mut self, offset: usize) {
-        let jump = self.chunk.code_array.len() - offset - 2;
-        if offset > 0xffff {
-            panic!("Too much code to jump over");
+    /// Makes the jump whose operand is at `offset` land on the next instruction.
+    fn patch_jump(&mut self, offset: usize) -> Result<(), CompileError> {
+        let jump = self.chunk.code_array.len() - offset - 3;
+        if jump > MAX_JUMP {
+            return Err(CompileError::JumpTooLarge);
         }
-        self.chunk.code_array[offset] = (jump >> 8) as u8;
-        self.chunk.code_array[offset + 1] = jump as u8;
+        self.chunk.code_array[offset] = (jump >> 16) as u8;
+        self.chunk.code_array[offset + 1] = (jump >> 8) as u8;
+        self.chunk.code_array[offset + 2] = jump as u8;
+        Ok(())
     }
 }
 
@@ -247,20 +873,22 @@ trait Compile {
 impl Compile for Stmt {
     fn compile(&self, compiler: &mut Compiler) -> Result<(), CompileError> {
         use crate::interpreter::parser::ast::StmtType::*;
+        // errors point at the innermost statement or expression they happen in
+        let enclosing = std::mem::replace(&mut compiler.current_range, self.range);
         match &self.intern {
             Expr(expr) => {
                 expr.compile(compiler)?;
-                compiler.add_instruction(Instruction::Pop, self.range);
+                compiler.add_instruction(Instruction::Pop, self.range)?;
             }
             Print(expr) => {
                 expr.compile(compiler)?;
-                compiler.add_instruction(Instruction::Print, self.range);
+                compiler.add_instruction(Instruction::Print, self.range)?;
             }
             Var(name, expr) => {
                 if let Some(expr) = expr {
                     expr.compile(compiler)?;
                 } else {
-                    compiler.add_instruction(Instruction::Constant(Value::Nil), self.range);
+                    compiler.add_instruction(Instruction::Constant(Value::Nil), self.range)?;
                 }
                 if compiler.scope_depth > 0 {
                     // check that the variable is not already defined in the current scope
@@ -269,13 +897,13 @@ impl Compile for Stmt {
                             break;
                         }
                         if local.name == *name {
-                            return Err(CompileError::VariableAlreadyDefined);
+                            return Err(CompileError::VariableAlreadyDefined(name.clone()));
                         }
                     }
-                    compiler.add_local(name.clone());
+                    compiler.add_local(name.clone())?;
                 } else {
-                    compiler
-                        .add_instruction(Instruction::DefineGlobal(ustr::ustr(name)), self.range);
+                    let slot = compiler.global_slot(name)?;
+                    compiler.add_instruction(Instruction::DefineGlobal(slot), self.range)?;
                 }
             }
             Block(stmts) => {
@@ -283,36 +911,68 @@ impl Compile for Stmt {
                 for stmt in stmts {
                     stmt.compile(compiler)?;
                 }
-                compiler.end_scope();
+                compiler.end_scope(self.range)?;
             }
             IfStmt(cond, then_branch, else_branch) => {
                 cond.compile(compiler)?;
-                let jump = compiler.emit_jump(Instruction::JumpIfFalse(0));
-                compiler.add_instruction(Instruction::Pop, self.range);
+                let jump = compiler.emit_jump(Instruction::JumpIfFalse(0), self.range)?;
+                compiler.add_instruction(Instruction::Pop, self.range)?;
                 then_branch.compile(compiler)?;
-                let end = compiler.emit_jump(Instruction::Jump(0));
-                compiler.patch_jump(jump);
-                compiler.add_instruction(Instruction::Pop, self.range);
+                let end = compiler.emit_jump(Instruction::Jump(0), self.range)?;
+                compiler.patch_jump(jump)?;
+                compiler.add_instruction(Instruction::Pop, self.range)?;
 
                 if let Some(else_branch) = else_branch {
                     else_branch.compile(compiler)?;
                 }
-                compiler.patch_jump(end);
+                compiler.patch_jump(end)?;
             }
             While(cond, body) => {
-                let loop_start = compiler.chunk.code_array.len();
-                cond.compile(compiler)?;
-
-                let exit_jump = compiler.emit_jump(Instruction::JumpIfFalse(0));
-                compiler.add_instruction(Instruction::Pop, self.range);
-                body.compile(compiler)?;
-                compiler.add_instruction(Instruction::Loop(loop_start), self.range);
-
-                compiler.patch_jump(exit_jump);
-                compiler.add_instruction(Instruction::Pop, self.range);
+                compiler.compile_loop(Some(cond), None, body, self.range)?;
+            }
+            For(initializer, cond, increment, body) => {
+                compiler.begin_scope();
+                if let Some(initializer) = initializer {
+                    initializer.compile(compiler)?;
+                }
+                compiler.compile_loop(cond.as_ref(), increment.as_ref(), body, self.range)?;
+                compiler.end_scope(self.range)?;
+            }
+            Break => compiler.emit_loop_exit(true, self.range)?,
+            Continue => compiler.emit_loop_exit(false, self.range)?,
+            Function(_, name, params, body) => {
+                // a local function is declared before its body is compiled, so
+                // that it can capture itself to recurse
+                let is_local = compiler.scope_depth > 0;
+                if is_local {
+                    compiler.add_local(name.clone())?;
+                }
+                let (function, upvalues) =
+                    compiler.compile_function(name, FunctionKind::Function, params, body)?;
+                let function = compiler.function_constant(function);
+                compiler.add_instruction(Instruction::Closure(function, upvalues), self.range)?;
+                if !is_local {
+                    let slot = compiler.global_slot(name)?;
+                    compiler.add_instruction(Instruction::DefineGlobal(slot), self.range)?;
+                }
+            }
+            Return(expr) => match compiler.kind {
+                FunctionKind::Script => return Err(CompileError::ReturnOutsideFunction),
+                FunctionKind::Initializer if self.returns_value() => {
+                    return Err(CompileError::ReturnFromInitializer)
+                }
+                // an initializer always returns `this`
+                FunctionKind::Initializer => compiler.emit_return(self.range)?,
+                FunctionKind::Function | FunctionKind::Method => {
+                    expr.compile(compiler)?;
+                    compiler.add_instruction(Instruction::Return, self.range)?;
+                }
+            },
+            Class(name, superclass, methods) => {
+                compiler.compile_class(name, superclass.as_ref(), methods, self.range)?;
             }
-            _ => todo!(),
         }
+        compiler.current_range = enclosing;
         Ok(())
     }
 }
@@ -320,12 +980,11 @@ impl Compile for Stmt {
 impl Compile for Expr {
     fn compile(&self, compiler: &mut Compiler) -> Result<(), CompileError> {
         use crate::interpreter::parser::ast::ExprType::*;
+        let enclosing = std::mem::replace(&mut compiler.current_range, self.range);
         match &*self.intern {
             Literal(value) => {
-                compiler.add_instruction(
-                    Instruction::Constant(value.clone().try_into()?),
-                    SourceCodeRange::new(0),
-                );
+                let value = compiler.literal_value(value)?;
+                compiler.add_instruction(Instruction::Constant(value), self.range)?;
             }
             Grouping(expr) => {
                 expr.compile(compiler)?;
@@ -334,10 +993,10 @@ impl Compile for Expr {
                 unary.compile(compiler)?;
                 match unary.intern {
                     parser::ast::UnaryType::Not => {
-                        compiler.add_instruction(Instruction::Not, self.range);
+                        compiler.add_instruction(Instruction::Not, self.range)?;
                     }
                     parser::ast::UnaryType::Neg => {
-                        compiler.add_instruction(Instruction::Negate, self.range);
+                        compiler.add_instruction(Instruction::Negate, self.range)?;
                     }
                 }
             }
@@ -346,37 +1005,37 @@ impl Compile for Expr {
                 binary.right.compile(compiler)?;
                 match binary.operator {
                     parser::ast::Operator::EqualEqual => {
-                        compiler.add_instruction(Instruction::Equal, self.range);
+                        compiler.add_instruction(Instruction::Equal, self.range)?;
                     }
                     parser::ast::Operator::NEqualEqual => {
-                        compiler.add_instruction(Instruction::Equal, self.range);
-                        compiler.add_instruction(Instruction::Not, self.range);
+                        compiler.add_instruction(Instruction::Equal, self.range)?;
+                        compiler.add_instruction(Instruction::Not, self.range)?;
                     }
                     parser::ast::Operator::Less => {
-                        compiler.add_instruction(Instruction::Less, self.range);
+                        compiler.add_instruction(Instruction::Less, self.range)?;
                     }
                     parser::ast::Operator::Leq => {
-                        compiler.add_instruction(Instruction::Greater, self.range);
-                        compiler.add_instruction(Instruction::Not, self.range);
+                        compiler.add_instruction(Instruction::Greater, self.range)?;
+                        compiler.add_instruction(Instruction::Not, self.range)?;
                     }
                     parser::ast::Operator::Greater => {
-                        compiler.add_instruction(Instruction::Greater, self.range);
+                        compiler.add_instruction(Instruction::Greater, self.range)?;
                     }
                     parser::ast::Operator::Greq => {
-                        compiler.add_instruction(Instruction::Less, self.range);
-                        compiler.add_instruction(Instruction::Not, self.range);
+                        compiler.add_instruction(Instruction::Less, self.range)?;
+                        compiler.add_instruction(Instruction::Not, self.range)?;
                     }
                     parser::ast::Operator::Plus => {
-                        compiler.add_instruction(Instruction::Add, self.range);
+                        compiler.add_instruction(Instruction::Add, self.range)?;
                     }
                     parser::ast::Operator::Minus => {
-                        compiler.add_instruction(Instruction::Subtract, self.range);
+                        compiler.add_instruction(Instruction::Subtract, self.range)?;
                     }
                     parser::ast::Operator::Times => {
-                        compiler.add_instruction(Instruction::Multiply, self.range);
+                        compiler.add_instruction(Instruction::Multiply, self.range)?;
                     }
                     parser::ast::Operator::Div => {
-                        compiler.add_instruction(Instruction::Divide, self.range);
+                        compiler.add_instruction(Instruction::Divide, self.range)?;
                     }
                 }
             }
@@ -384,47 +1043,91 @@ impl Compile for Expr {
                 logical.left.compile(compiler)?;
                 match logical.operator {
                     parser::ast::LogicalOperator::And => {
-                        let jump = compiler.emit_jump(Instruction::JumpIfFalse(0));
-                        compiler.add_instruction(Instruction::Pop, self.range);
+                        let jump = compiler.emit_jump(Instruction::JumpIfFalse(0), self.range)?;
+                        compiler.add_instruction(Instruction::Pop, self.range)?;
                         logical.right.compile(compiler)?;
-                        compiler.patch_jump(jump);
+                        compiler.patch_jump(jump)?;
                     }
                     parser::ast::LogicalOperator::Or => {
-                        let else_jump = compiler.emit_jump(Instruction::JumpIfFalse(0));
-                        let end_jump = compiler.emit_jump(Instruction::Jump(0));
-                        compiler.patch_jump(else_jump);
-                        compiler.add_instruction(Instruction::Pop, self.range);
+                        let else_jump =
+                            compiler.emit_jump(Instruction::JumpIfFalse(0), self.range)?;
+                        let end_jump = compiler.emit_jump(Instruction::Jump(0), self.range)?;
+                        compiler.patch_jump(else_jump)?;
+                        compiler.add_instruction(Instruction::Pop, self.range)?;
                         logical.right.compile(compiler)?;
-                        compiler.patch_jump(end_jump);
+                        compiler.patch_jump(end_jump)?;
                     }
                 }
             }
-            Variable(name) => {
-                if let Ok(idx) = compiler.resolve_local(name) {
-                    compiler.add_instruction(
-                        Instruction::GetLocal(idx.try_into().unwrap()),
-                        self.range,
-                    );
-                } else {
-                    compiler.add_instruction(Instruction::GetGlobal(ustr::ustr(name)), self.range);
-                }
-            }
+            Variable(name) => compiler.get_variable(name, self.range)?,
             Assign(name, expr) => {
                 expr.compile(compiler)?;
-                if let Ok(idx) = compiler.resolve_local(name) {
-                    compiler.add_instruction(
-                        Instruction::SetLocal(idx.try_into().unwrap()),
-                        self.range,
-                    );
+                if let Some(idx) = compiler.resolve_local(name) {
+                    compiler.add_instruction(Instruction::SetLocal(idx), self.range)?;
+                } else if let Some(idx) = compiler.resolve_upvalue(name)? {
+                    compiler.add_instruction(Instruction::SetUpvalue(idx), self.range)?;
                 } else {
-                    compiler.add_instruction(Instruction::SetGlobal(ustr::ustr(name)), self.range);
+                    let slot = compiler.global_slot(name)?;
+                    compiler.add_instruction(Instruction::SetGlobal(slot), self.range)?;
+                }
+            }
+            Call(call) => {
+                // the parser allows at most 255 arguments
+                let arg_count = call.arguments.len().try_into().unwrap();
+                // method calls skip creating a bound method
+                match &*call.callee.intern {
+                    Get(object, name) => {
+                        object.compile(compiler)?;
+                        for argument in &call.arguments {
+                            argument.compile(compiler)?;
+                        }
+                        compiler.add_instruction(
+                            Instruction::Invoke(name.clone(), arg_count),
+                            self.range,
+                        )?;
+                    }
+                    Super(method) => {
+                        compiler.get_variable("this", self.range)?;
+                        for argument in &call.arguments {
+                            argument.compile(compiler)?;
+                        }
+                        compiler.get_super(self.range)?;
+                        compiler.add_instruction(
+                            Instruction::SuperInvoke(method.clone(), arg_count),
+                            self.range,
+                        )?;
+                    }
+                    _ => {
+                        call.callee.compile(compiler)?;
+                        for argument in &call.arguments {
+                            argument.compile(compiler)?;
+                        }
+                        compiler.add_instruction(Instruction::Call(arg_count), self.range)?;
+                    }
                 }
             }
-            // Call(call) => todo!(),
-            // Get(expr, _) => todo!(),
-            // Set(expr, _, expr1) => todo!(),
-            _ => todo!(),
+            Get(object, name) => {
+                object.compile(compiler)?;
+                compiler.add_instruction(Instruction::GetProperty(name.clone()), self.range)?;
+            }
+            Set(object, name, value) => {
+                object.compile(compiler)?;
+                value.compile(compiler)?;
+                compiler.add_instruction(Instruction::SetProperty(name.clone()), self.range)?;
+            }
+            This => {
+                if compiler.classes.is_empty() {
+                    return Err(CompileError::ThisOutsideClass);
+                }
+                compiler.get_variable("this", self.range)?;
+            }
+            Super(method) => {
+                compiler.get_variable("this", self.range)?;
+                compiler.get_super(self.range)?;
+                compiler.add_instruction(Instruction::GetSuper(method.clone()), self.range)?;
+            }
         }
+        compiler.current_range = enclosing;
         Ok(())
     }
 }
@@ -2,23 +2,28 @@ use std::fmt::Display;
 
 use super::{Instruction, Value};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OpCode {
     OpReturn = 0,
     OpPrint,
     OpPop,
     OpConstant,
-    /// Has a pointer on the Host machine after it as the index into the constant pool
+    /// Followed by two bytes holding the slot of the global in the global table
     OpDefineGlobal,
-    /// Has a pointer on the Host machine after it as the index into the constant pool
+    /// Followed by two bytes holding the slot of the global in the global table
     OpGetGlobal,
-    /// Has a pointer on the Host machine after it as the index into the constant pool
+    /// Followed by two bytes holding the slot of the global in the global table
     OpSetGlobal,
-    /// Has a pointer on the Host machine after it as the index into the constant pool
+    /// Followed by the stack slot of the local, relative to the call frame
     OpGetLocal,
-    /// Has a pointer on the Host machine after it as the index into the constant pool
+    /// Followed by the stack slot of the local, relative to the call frame
     OpSetLocal,
+    /// Followed by two bytes holding how far to jump forward if the value on
+    /// top of the stack is falsey
     OpJumpIfFalse,
+    /// Followed by two bytes holding how far to jump forward
     OpJump,
+    /// Followed by two bytes holding how far to jump backward
     OpLoop,
     OpNil,
     OpFalse,
@@ -33,6 +38,103 @@ pub enum OpCode {
     OpSubtract,
     OpMultiply,
     OpDivide,
+    /// Followed by the number of arguments
+    OpCall,
+    /// Followed by the index of the function in the constant pool, then an
+    /// is-local byte and two bytes holding the index for every captured
+    /// variable
+    OpClosure,
+    OpGetUpvalue,
+    OpSetUpvalue,
+    OpCloseUpvalue,
+    /// Followed by the index of the class name in the constant pool
+    OpClass,
+    OpInherit,
+    /// Followed by the index of the method name in the constant pool
+    OpMethod,
+    /// Followed by the index of the property name in the constant pool
+    OpGetProperty,
+    /// Followed by the index of the property name in the constant pool
+    OpSetProperty,
+    /// Followed by the index of the method name and the number of arguments
+    OpInvoke,
+    /// Followed by the index of the method name in the constant pool
+    OpGetSuper,
+    /// Followed by the index of the method name and the number of arguments
+    OpSuperInvoke,
+    /// `OpLess, OpNot` fused by the optimizer, the compiler never emits it
+    OpGreaterEqual,
+    /// `OpGreater, OpNot` fused by the optimizer
+    OpLessEqual,
+    /// `OpEq, OpNot` fused by the optimizer
+    OpNotEqual,
+    /// Followed by two bytes holding the stack slot of the local, for slots
+    /// past 255
+    OpGetLocalLong,
+    /// Followed by two bytes holding the stack slot of the local, for slots
+    /// past 255
+    OpSetLocalLong,
+    /// Followed by three bytes holding the distance, for jumps too long for
+    /// two bytes
+    OpJumpIfFalseLong,
+    /// Followed by three bytes holding the distance
+    OpJumpLong,
+    /// Followed by three bytes holding the distance
+    OpLoopLong,
+    /// Like `OpClosure`, with three bytes holding the index of the function,
+    /// for constants past 255
+    OpClosureLong,
+    /// Followed by three bytes holding the index of the class name
+    OpClassLong,
+    /// Followed by three bytes holding the index of the method name
+    OpMethodLong,
+    /// Followed by three bytes holding the index of the property name
+    OpGetPropertyLong,
+    /// Followed by three bytes holding the index of the property name
+    OpSetPropertyLong,
+    /// Followed by three bytes holding the index of the method name, then the
+    /// number of arguments
+    OpInvokeLong,
+    /// Followed by three bytes holding the index of the method name
+    OpGetSuperLong,
+    /// Followed by three bytes holding the index of the method name, then the
+    /// number of arguments
+    OpSuperInvokeLong,
+}
+
+impl OpCode {
+    /// The form of an instruction referring to a constant that takes three
+    /// bytes for its index instead of one.
+    pub(crate) fn long(self) -> Self {
+        match self {
+            OpCode::OpConstant => OpCode::OpConstantLong,
+            OpCode::OpClosure => OpCode::OpClosureLong,
+            OpCode::OpClass => OpCode::OpClassLong,
+            OpCode::OpMethod => OpCode::OpMethodLong,
+            OpCode::OpGetProperty => OpCode::OpGetPropertyLong,
+            OpCode::OpSetProperty => OpCode::OpSetPropertyLong,
+            OpCode::OpInvoke => OpCode::OpInvokeLong,
+            OpCode::OpGetSuper => OpCode::OpGetSuperLong,
+            OpCode::OpSuperInvoke => OpCode::OpSuperInvokeLong,
+            op => op,
+        }
+    }
+
+    /// Whether the index of the instruction's constant takes three bytes.
+    pub(crate) fn is_long_constant(self) -> bool {
+        matches!(
+            self,
+            OpCode::OpConstantLong
+                | OpCode::OpClosureLong
+                | OpCode::OpClassLong
+                | OpCode::OpMethodLong
+                | OpCode::OpGetPropertyLong
+                | OpCode::OpSetPropertyLong
+                | OpCode::OpInvokeLong
+                | OpCode::OpGetSuperLong
+                | OpCode::OpSuperInvokeLong
+        )
+    }
 }
 
 impl TryFrom<&Instruction> for OpCode {
@@ -64,6 +166,19 @@ impl TryFrom<&Instruction> for OpCode {
             Instruction::Jump(_) => Err(()),
             Instruction::JumpIfFalse(_) => Err(()),
             Instruction::Loop(_) => Err(()),
+            Instruction::Call(_) => Err(()),
+            Instruction::Closure(..) => Err(()),
+            Instruction::GetUpvalue(_) => Err(()),
+            Instruction::SetUpvalue(_) => Err(()),
+            Instruction::CloseUpvalue => Ok(OpCode::OpCloseUpvalue),
+            Instruction::Inherit => Ok(OpCode::OpInherit),
+            Instruction::Class(_) => Err(()),
+            Instruction::Method(_) => Err(()),
+            Instruction::GetProperty(_) => Err(()),
+            Instruction::SetProperty(_) => Err(()),
+            Instruction::Invoke(..) => Err(()),
+            Instruction::GetSuper(_) => Err(()),
+            Instruction::SuperInvoke(..) => Err(()),
         }
     }
 }
@@ -96,6 +211,35 @@ impl Display for OpCode {
             OpCode::OpJumpIfFalse => write!(f, "OP_JUMP_IF_FALSE"),
             OpCode::OpJump => write!(f, "OP_JUMP"),
             OpCode::OpLoop => write!(f, "OP_LOOP"),
+            OpCode::OpCall => write!(f, "OP_CALL"),
+            OpCode::OpClosure => write!(f, "OP_CLOSURE"),
+            OpCode::OpGetUpvalue => write!(f, "OP_GET_UPVALUE"),
+            OpCode::OpSetUpvalue => write!(f, "OP_SET_UPVALUE"),
+            OpCode::OpCloseUpvalue => write!(f, "OP_CLOSE_UPVALUE"),
+            OpCode::OpClass => write!(f, "OP_CLASS"),
+            OpCode::OpInherit => write!(f, "OP_INHERIT"),
+            OpCode::OpMethod => write!(f, "OP_METHOD"),
+            OpCode::OpGetProperty => write!(f, "OP_GET_PROPERTY"),
+            OpCode::OpSetProperty => write!(f, "OP_SET_PROPERTY"),
+            OpCode::OpInvoke => write!(f, "OP_INVOKE"),
+            OpCode::OpGetSuper => write!(f, "OP_GET_SUPER"),
+            OpCode::OpSuperInvoke => write!(f, "OP_SUPER_INVOKE"),
+            OpCode::OpGreaterEqual => write!(f, "OP_GREATER_EQUAL"),
+            OpCode::OpLessEqual => write!(f, "OP_LESS_EQUAL"),
+            OpCode::OpNotEqual => write!(f, "OP_NOT_EQUAL"),
+            OpCode::OpGetLocalLong => write!(f, "OP_GET_LOCAL_LONG"),
+            OpCode::OpSetLocalLong => write!(f, "OP_SET_LOCAL_LONG"),
+            OpCode::OpJumpIfFalseLong => write!(f, "OP_JUMP_IF_FALSE_LONG"),
+            OpCode::OpJumpLong => write!(f, "OP_JUMP_LONG"),
+            OpCode::OpLoopLong => write!(f, "OP_LOOP_LONG"),
+            OpCode::OpClosureLong => write!(f, "OP_CLOSURE_LONG"),
+            OpCode::OpClassLong => write!(f, "OP_CLASS_LONG"),
+            OpCode::OpMethodLong => write!(f, "OP_METHOD_LONG"),
+            OpCode::OpGetPropertyLong => write!(f, "OP_GET_PROPERTY_LONG"),
+            OpCode::OpSetPropertyLong => write!(f, "OP_SET_PROPERTY_LONG"),
+            OpCode::OpInvokeLong => write!(f, "OP_INVOKE_LONG"),
+            OpCode::OpGetSuperLong => write!(f, "OP_GET_SUPER_LONG"),
+            OpCode::OpSuperInvokeLong => write!(f, "OP_SUPER_INVOKE_LONG"),
         }
     }
 }
@@ -129,6 +273,35 @@ impl TryFrom<u8> for OpCode {
         const OP_JUMP_IF_FALSE: u8 = OpCode::OpJumpIfFalse as u8;
         const OP_JUMP: u8 = OpCode::OpJump as u8;
         const OP_LOOP: u8 = OpCode::OpLoop as u8;
+        const OP_CALL: u8 = OpCode::OpCall as u8;
+        const OP_CLOSURE: u8 = OpCode::OpClosure as u8;
+        const OP_GET_UPVALUE: u8 = OpCode::OpGetUpvalue as u8;
+        const OP_SET_UPVALUE: u8 = OpCode::OpSetUpvalue as u8;
+        const OP_CLOSE_UPVALUE: u8 = OpCode::OpCloseUpvalue as u8;
+        const OP_CLASS: u8 = OpCode::OpClass as u8;
+        const OP_INHERIT: u8 = OpCode::OpInherit as u8;
+        const OP_METHOD: u8 = OpCode::OpMethod as u8;
+        const OP_GET_PROPERTY: u8 = OpCode::OpGetProperty as u8;
+        const OP_SET_PROPERTY: u8 = OpCode::OpSetProperty as u8;
+        const OP_INVOKE: u8 = OpCode::OpInvoke as u8;
+        const OP_GET_SUPER: u8 = OpCode::OpGetSuper as u8;
+        const OP_SUPER_INVOKE: u8 = OpCode::OpSuperInvoke as u8;
+        const OP_GREATER_EQUAL: u8 = OpCode::OpGreaterEqual as u8;
+        const OP_LESS_EQUAL: u8 = OpCode::OpLessEqual as u8;
+        const OP_NOT_EQUAL: u8 = OpCode::OpNotEqual as u8;
+        const OP_GET_LOCAL_LONG: u8 = OpCode::OpGetLocalLong as u8;
+        const OP_SET_LOCAL_LONG: u8 = OpCode::OpSetLocalLong as u8;
+        const OP_JUMP_IF_FALSE_LONG: u8 = OpCode::OpJumpIfFalseLong as u8;
+        const OP_JUMP_LONG: u8 = OpCode::OpJumpLong as u8;
+        const OP_LOOP_LONG: u8 = OpCode::OpLoopLong as u8;
+        const OP_CLOSURE_LONG: u8 = OpCode::OpClosureLong as u8;
+        const OP_CLASS_LONG: u8 = OpCode::OpClassLong as u8;
+        const OP_METHOD_LONG: u8 = OpCode::OpMethodLong as u8;
+        const OP_GET_PROPERTY_LONG: u8 = OpCode::OpGetPropertyLong as u8;
+        const OP_SET_PROPERTY_LONG: u8 = OpCode::OpSetPropertyLong as u8;
+        const OP_INVOKE_LONG: u8 = OpCode::OpInvokeLong as u8;
+        const OP_GET_SUPER_LONG: u8 = OpCode::OpGetSuperLong as u8;
+        const OP_SUPER_INVOKE_LONG: u8 = OpCode::OpSuperInvokeLong as u8;
         match value {
             OP_RETURN => Ok(OpCode::OpReturn),
             OP_PRINT => Ok(OpCode::OpPrint),
@@ -155,6 +328,35 @@ impl TryFrom<u8> for OpCode {
             OP_JUMP_IF_FALSE => Ok(OpCode::OpJumpIfFalse),
             OP_JUMP => Ok(OpCode::OpJump),
             OP_LOOP => Ok(OpCode::OpLoop),
+            OP_CALL => Ok(OpCode::OpCall),
+            OP_CLOSURE => Ok(OpCode::OpClosure),
+            OP_GET_UPVALUE => Ok(OpCode::OpGetUpvalue),
+            OP_SET_UPVALUE => Ok(OpCode::OpSetUpvalue),
+            OP_CLOSE_UPVALUE => Ok(OpCode::OpCloseUpvalue),
+            OP_CLASS => Ok(OpCode::OpClass),
+            OP_INHERIT => Ok(OpCode::OpInherit),
+            OP_METHOD => Ok(OpCode::OpMethod),
+            OP_GET_PROPERTY => Ok(OpCode::OpGetProperty),
+            OP_SET_PROPERTY => Ok(OpCode::OpSetProperty),
+            OP_INVOKE => Ok(OpCode::OpInvoke),
+            OP_GET_SUPER => Ok(OpCode::OpGetSuper),
+            OP_SUPER_INVOKE => Ok(OpCode::OpSuperInvoke),
+            OP_GREATER_EQUAL => Ok(OpCode::OpGreaterEqual),
+            OP_LESS_EQUAL => Ok(OpCode::OpLessEqual),
+            OP_NOT_EQUAL => Ok(OpCode::OpNotEqual),
+            OP_GET_LOCAL_LONG => Ok(OpCode::OpGetLocalLong),
+            OP_SET_LOCAL_LONG => Ok(OpCode::OpSetLocalLong),
+            OP_JUMP_IF_FALSE_LONG => Ok(OpCode::OpJumpIfFalseLong),
+            OP_JUMP_LONG => Ok(OpCode::OpJumpLong),
+            OP_LOOP_LONG => Ok(OpCode::OpLoopLong),
+            OP_CLOSURE_LONG => Ok(OpCode::OpClosureLong),
+            OP_CLASS_LONG => Ok(OpCode::OpClassLong),
+            OP_METHOD_LONG => Ok(OpCode::OpMethodLong),
+            OP_GET_PROPERTY_LONG => Ok(OpCode::OpGetPropertyLong),
+            OP_SET_PROPERTY_LONG => Ok(OpCode::OpSetPropertyLong),
+            OP_INVOKE_LONG => Ok(OpCode::OpInvokeLong),
+            OP_GET_SUPER_LONG => Ok(OpCode::OpGetSuperLong),
+            OP_SUPER_INVOKE_LONG => Ok(OpCode::OpSuperInvokeLong),
             _ => Err(()),
         }
     }
@@ -0,0 +1,634 @@
+//! A peephole optimizer over compiled bytecode.
+//!
+//! A chunk is decoded into a list of instructions whose jumps refer to the
+//! instruction they land on, rewritten until nothing changes anymore and
+//! encoded again, so offsets are recomputed afterwards. Every instruction
+//! keeps the source range it was compiled from.
+//!
+//! The rewrites are:
+//! - `OpLess, OpNot`, `OpGreater, OpNot` and `OpEq, OpNot` become
+//!   `OpGreaterEqual`, `OpLessEqual` and `OpNotEqual`
+//! - jumps to an `OpJump` go directly to where that one goes, and
+//!   `OpJumpIfFalse` to another `OpJumpIfFalse` skips it as the condition
+//!   is still the same
+//! - jumps to the next instruction are removed
+//! - a value pushed without side effects and popped right away is never
+//!   pushed, and `x = x;` for a local is removed
+//! - storing a variable, popping it and loading it again keeps it on the stack
+//!
+//! Encoding gives every jump the shortest form its distance fits into. The
+//! compiler relies on that for every chunk, as it emits forward jumps in their
+//! long form before it knows how far they go.
+
+use super::{Chunk, Heap, Object, OpCode, Program, SourceCodeRange, Value};
+
+impl Program {
+    /// Optimizes the top-level code and every function of the program.
+    pub fn optimize(&mut self) {
+        optimize_functions(&self.chunk, &mut self.heap);
+        let chunk = std::mem::replace(&mut self.chunk, Chunk::new());
+        self.chunk = optimize_chunk(chunk, &self.heap);
+    }
+}
+
+/// Optimizes the functions declared in `chunk`.
+fn optimize_functions(chunk: &Chunk, heap: &mut Heap) {
+    for constant in &chunk.constant_pool {
+        let Value::Obj(obj) = *constant else {
+            continue;
+        };
+        let Object::Function(function) = heap.get_mut(obj) else {
+            continue;
+        };
+        // functions are only shared once the program runs
+        let Some(function) = std::rc::Rc::get_mut(function) else {
+            continue;
+        };
+        let mut chunk = std::mem::replace(&mut function.chunk, Chunk::new());
+        optimize_functions(&chunk, heap);
+        chunk = optimize_chunk(chunk, heap);
+        let Object::Function(function) = heap.get_mut(obj) else {
+            unreachable!()
+        };
+        std::rc::Rc::get_mut(function).unwrap().chunk = chunk;
+    }
+}
+
+/// Re-encodes `chunk` without optimizing it, so its jumps are as short as
+/// possible.
+pub(super) fn narrow_jumps(chunk: Chunk, heap: &Heap) -> Chunk {
+    let instructions = decode(&chunk, heap);
+    encode(chunk, instructions)
+}
+
+struct Instruction {
+    /// Jumps are always in their short form, the long one is picked when encoding
+    op: OpCode,
+    /// The operand bytes, for jumps they are filled in when encoding
+    operands: Vec<u8>,
+    range: SourceCodeRange,
+    /// The index of the instruction a jump lands on
+    target: Option<usize>,
+    /// Whether the instruction was optimized away
+    removed: bool,
+}
+
+fn optimize_chunk(chunk: Chunk, heap: &Heap) -> Chunk {
+    let mut instructions = decode(&chunk, heap);
+    while fuse_comparisons(&mut instructions)
+        | thread_jumps(&mut instructions)
+        | remove_dead_code(&mut instructions)
+    {
+        // the index every instruction has once the removed ones are gone. A
+        // jump to a removed instruction lands on the next one that is left
+        let mut new_indexes = Vec::with_capacity(instructions.len() + 1);
+        let mut live = 0;
+        for instruction in &instructions {
+            new_indexes.push(live);
+            live += !instruction.removed as usize;
+        }
+        new_indexes.push(live);
+        instructions.retain(|instruction| !instruction.removed);
+        for instruction in &mut instructions {
+            if let Some(target) = instruction.target {
+                instruction.target = Some(new_indexes[target]);
+            }
+        }
+    }
+    encode(chunk, instructions)
+}
+
+fn decode(chunk: &Chunk, heap: &Heap) -> Vec<Instruction> {
+    let code = &chunk.code_array;
+    let mut instructions = Vec::new();
+    // the index of the instruction at every offset, to resolve jumps
+    let mut starts = vec![0; code.len() + 1];
+    let mut jumps = Vec::new();
+    let mut offset = 0;
+    while offset < code.len() {
+        let op: OpCode = code[offset]
+            .try_into()
+            .expect("the compiler only emits valid opcodes");
+        use OpCode::*;
+        let len = match op {
+            OpConstant | OpGetLocal | OpSetLocal | OpCall | OpGetUpvalue | OpSetUpvalue
+            | OpClass | OpMethod | OpGetProperty | OpSetProperty | OpGetSuper => 2,
+            OpDefineGlobal | OpGetGlobal | OpSetGlobal | OpJump | OpJumpIfFalse | OpLoop
+            | OpInvoke | OpSuperInvoke | OpGetLocalLong | OpSetLocalLong => 3,
+            OpConstantLong | OpJumpLong | OpJumpIfFalseLong | OpLoopLong | OpClassLong
+            | OpMethodLong | OpGetPropertyLong | OpSetPropertyLong | OpGetSuperLong => 4,
+            OpInvokeLong | OpSuperInvokeLong => 5,
+            OpClosure | OpClosureLong => {
+                let width = if op == OpClosure { 1 } else { 3 };
+                let idx = code[offset + 1..offset + 1 + width]
+                    .iter()
+                    .fold(0, |idx, &byte| idx << 8 | byte as usize);
+                let Value::Obj(function) = chunk.constant_pool[idx] else {
+                    unreachable!("closures are created from functions")
+                };
+                1 + width + 3 * heap.function(function).upvalue_count
+            }
+            _ => 1,
+        };
+        starts[offset] = instructions.len();
+        let operands = &code[offset + 1..offset + len];
+        let op = match op {
+            OpJump | OpJumpIfFalse | OpLoop | OpJumpLong | OpJumpIfFalseLong | OpLoopLong => {
+                let jump = operands
+                    .iter()
+                    .fold(0, |jump, &byte| jump << 8 | byte as usize);
+                let target = match op {
+                    OpLoop | OpLoopLong => offset + len - jump,
+                    _ => offset + len + jump,
+                };
+                jumps.push((instructions.len(), target));
+                match op {
+                    OpJumpLong => OpJump,
+                    OpJumpIfFalseLong => OpJumpIfFalse,
+                    OpLoopLong => OpLoop,
+                    op => op,
+                }
+            }
+            op => op,
+        };
+        instructions.push(Instruction {
+            op,
+            operands: operands.to_vec(),
+            range: chunk.range(offset),
+            target: None,
+            removed: false,
+        });
+        offset += len;
+    }
+    starts[code.len()] = instructions.len();
+    for (idx, target) in jumps {
+        instructions[idx].target = Some(starts[target]);
+    }
+    instructions
+}
+
+fn encode(mut chunk: Chunk, instructions: Vec<Instruction>) -> Chunk {
+    // every jump starts out short, the ones whose distance doesn't fit are
+    // made long until all do. That only makes the code longer, so it ends
+    let mut long = vec![false; instructions.len()];
+    let offsets = loop {
+        let mut offsets = Vec::with_capacity(instructions.len() + 1);
+        let mut offset = 0;
+        for (idx, instruction) in instructions.iter().enumerate() {
+            offsets.push(offset);
+            offset += 1 + match instruction.target {
+                Some(_) if long[idx] => 3,
+                Some(_) => 2,
+                None => instruction.operands.len(),
+            };
+        }
+        offsets.push(offset);
+
+        let mut widened = false;
+        for (idx, instruction) in instructions.iter().enumerate() {
+            if let Some(target) = instruction.target {
+                if !long[idx] && offsets[idx + 1].abs_diff(offsets[target]) > u16::MAX as usize {
+                    long[idx] = true;
+                    widened = true;
+                }
+            }
+        }
+        if !widened {
+            break offsets;
+        }
+    };
+
+    chunk.code_array.clear();
+    chunk.ranges.clear();
+    for (idx, instruction) in instructions.iter().enumerate() {
+        let mut op = instruction.op;
+        let mut operands = instruction.operands.clone();
+        if let Some(target) = instruction.target {
+            let next = offsets[idx + 1];
+            let target = offsets[target];
+            // threading may turn a jump forward into one backward
+            let jump = if target >= next {
+                if op == OpCode::OpLoop {
+                    op = OpCode::OpJump;
+                }
+                target - next
+            } else {
+                op = OpCode::OpLoop;
+                next - target
+            };
+            if long[idx] {
+                op = match op {
+                    OpCode::OpJump => OpCode::OpJumpLong,
+                    OpCode::OpJumpIfFalse => OpCode::OpJumpIfFalseLong,
+                    _ => OpCode::OpLoopLong,
+                };
+                // the compiler made sure the jump fits with every jump long
+                operands = vec![(jump >> 16) as u8, (jump >> 8) as u8, jump as u8];
+            } else {
+                operands = vec![(jump >> 8) as u8, jump as u8];
+            }
+        }
+        chunk.push_code(op as u8, instruction.range);
+        for operand in operands {
+            chunk.push_code(operand, instruction.range);
+        }
+    }
+    chunk
+}
+
+/// The first instruction after `idx` that was not removed.
+fn next_live(instructions: &[Instruction], idx: usize) -> usize {
+    let mut next = idx + 1;
+    while next < instructions.len() && instructions[next].removed {
+        next += 1;
+    }
+    next
+}
+
+/// For every instruction whether a jump lands on it.
+fn jump_targets(instructions: &[Instruction]) -> Vec<bool> {
+    let mut targets = vec![false; instructions.len() + 1];
+    for instruction in instructions {
+        if let Some(target) = instruction.target {
+            targets[target] = true;
+        }
+    }
+    targets
+}
+
+fn fuse_comparisons(instructions: &mut [Instruction]) -> bool {
+    let targets = jump_targets(instructions);
+    let mut changed = false;
+    for idx in 1..instructions.len() {
+        if instructions[idx].op != OpCode::OpNot
+            || instructions[idx].removed
+            || instructions[idx - 1].removed
+            || targets[idx]
+        {
+            continue;
+        }
+        let fused = match instructions[idx - 1].op {
+            OpCode::OpLess => OpCode::OpGreaterEqual,
+            OpCode::OpGreater => OpCode::OpLessEqual,
+            OpCode::OpEq => OpCode::OpNotEqual,
+            _ => continue,
+        };
+        instructions[idx - 1].op = fused;
+        instructions[idx].removed = true;
+        changed = true;
+    }
+    changed
+}
+
+fn thread_jumps(instructions: &mut [Instruction]) -> bool {
+    let mut changed = false;
+    for idx in 0..instructions.len() {
+        let Some(mut target) = instructions[idx].target else {
+            continue;
+        };
+        if instructions[idx].removed {
+            continue;
+        }
+        let conditional = instructions[idx].op == OpCode::OpJumpIfFalse;
+        // a cycle of jumps has to end somewhere
+        for _ in 0..instructions.len() {
+            let Some(next) = instructions.get(target) else {
+                break;
+            };
+            let follow = match next.op {
+                OpCode::OpJump | OpCode::OpLoop => true,
+                // the condition is still on the stack, so it jumps as well
+                OpCode::OpJumpIfFalse => conditional,
+                _ => false,
+            };
+            match next.target {
+                // a conditional jump can only go forward
+                Some(next) if follow && next != target && (!conditional || next > idx) => {
+                    target = next
+                }
+                _ => break,
+            }
+        }
+        if instructions[idx].target != Some(target) {
+            instructions[idx].target = Some(target);
+            changed = true;
+        }
+        // an unconditional jump to the next instruction does nothing
+        if !conditional && next_live(instructions, idx) == target {
+            instructions[idx].removed = true;
+            changed = true;
+        }
+    }
+    changed
+}
+
+fn remove_dead_code(instructions: &mut [Instruction]) -> bool {
+    use OpCode::*;
+    let targets = jump_targets(instructions);
+    let live = |instructions: &[Instruction], idx: usize| {
+        instructions
+            .get(idx)
+            .filter(|instruction| !instruction.removed)
+            .map(|instruction| (instruction.op, instruction.operands.clone()))
+    };
+    let mut changed = false;
+    for idx in 1..instructions.len() {
+        let (Some((first, first_operand)), Some((second, _))) =
+            (live(instructions, idx - 1), live(instructions, idx))
+        else {
+            continue;
+        };
+        let third = live(instructions, idx + 1);
+        if targets[idx] {
+            continue;
+        }
+        // pushing a value and popping it again
+        let pure_push = matches!(
+            first,
+            OpConstant
+                | OpConstantLong
+                | OpNil
+                | OpTrue
+                | OpFalse
+                | OpGetLocal
+                | OpGetLocalLong
+                | OpGetUpvalue
+        );
+        if pure_push && second == OpPop {
+            instructions[idx - 1].removed = true;
+            instructions[idx].removed = true;
+            changed = true;
+            continue;
+        }
+        let Some((third, third_operand)) = third else {
+            continue;
+        };
+        if targets[idx + 1] {
+            continue;
+        }
+        // `x = x;` loads and stores the same local
+        if matches!(
+            (first, second, third),
+            (OpGetLocal, OpSetLocal, OpPop) | (OpGetLocalLong, OpSetLocalLong, OpPop)
+        ) && instructions[idx].operands == first_operand
+        {
+            for instruction in &mut instructions[idx - 1..=idx + 1] {
+                instruction.removed = true;
+            }
+            changed = true;
+            continue;
+        }
+        // storing a variable, popping it and loading it again
+        if first_operand == third_operand
+            && matches!(
+                (first, second, third),
+                (OpSetLocal, OpPop, OpGetLocal)
+                    | (OpSetLocalLong, OpPop, OpGetLocalLong)
+                    | (OpSetUpvalue, OpPop, OpGetUpvalue)
+                    | (OpSetGlobal, OpPop, OpGetGlobal)
+            )
+        {
+            instructions[idx].removed = true;
+            instructions[idx + 1].removed = true;
+            changed = true;
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use OpCode::*;
+
+    /// A chunk of `instructions`, each compiled from the line it is paired with.
+    fn chunk(instructions: &[(&[u8], usize)]) -> Chunk {
+        let mut chunk = Chunk::new();
+        for &(bytes, line) in instructions {
+            for &byte in bytes {
+                chunk.push_code(byte, SourceCodeRange::new(line));
+            }
+        }
+        chunk.constant_pool.push(Value::Number(1.0));
+        chunk
+    }
+
+    fn optimized(instructions: &[(&[u8], usize)]) -> Vec<u8> {
+        optimize_chunk(chunk(instructions), &Heap::new()).code_array
+    }
+
+    #[test]
+    fn fuses_comparisons() {
+        let code = optimized(&[
+            (&[OpTrue as u8, OpFalse as u8, OpLess as u8, OpNot as u8], 1),
+            (
+                &[OpTrue as u8, OpFalse as u8, OpGreater as u8, OpNot as u8],
+                1,
+            ),
+            (&[OpTrue as u8, OpFalse as u8, OpEq as u8, OpNot as u8], 1),
+            (&[OpReturn as u8], 1),
+        ]);
+        let mut expected = [
+            [OpTrue as u8, OpFalse as u8, OpGreaterEqual as u8],
+            [OpTrue as u8, OpFalse as u8, OpLessEqual as u8],
+            [OpTrue as u8, OpFalse as u8, OpNotEqual as u8],
+        ]
+        .concat();
+        expected.push(OpReturn as u8);
+        assert_eq!(code, expected);
+    }
+
+    #[test]
+    fn keeps_comparisons_a_jump_lands_between() {
+        let code = [
+            OpTrue as u8,
+            OpFalse as u8,
+            OpLess as u8,
+            OpJumpIfFalse as u8,
+            0,
+            0,
+            OpNot as u8,
+            OpReturn as u8,
+        ];
+        assert_eq!(optimized(&[(&code, 1)]), code);
+    }
+
+    #[test]
+    fn threads_jumps() {
+        let code = optimized(&[
+            // jumps to the second jump
+            (&[OpJump as u8, 0, 1], 1),
+            (&[OpNil as u8], 2),
+            (&[OpJump as u8, 0, 1], 3),
+            (&[OpNil as u8, OpNil as u8, OpReturn as u8], 4),
+        ]);
+        let expected = [
+            OpJump as u8,
+            0,
+            5,
+            OpNil as u8,
+            OpJump as u8,
+            0,
+            1,
+            OpNil as u8,
+            OpNil as u8,
+            OpReturn as u8,
+        ];
+        assert_eq!(code, expected);
+    }
+
+    #[test]
+    fn threads_conditional_jumps_through_the_same_condition() {
+        let code = optimized(&[
+            (&[OpTrue as u8, OpJumpIfFalse as u8, 0, 1], 1),
+            (&[OpNil as u8], 2),
+            (&[OpJumpIfFalse as u8, 0, 1], 3),
+            (&[OpNil as u8, OpNil as u8, OpReturn as u8], 4),
+        ]);
+        let expected = [
+            OpTrue as u8,
+            OpJumpIfFalse as u8,
+            0,
+            5,
+            OpNil as u8,
+            OpJumpIfFalse as u8,
+            0,
+            1,
+            OpNil as u8,
+            OpNil as u8,
+            OpReturn as u8,
+        ];
+        assert_eq!(code, expected);
+    }
+
+    #[test]
+    fn removes_jumps_to_the_next_instruction() {
+        let code = optimized(&[(&[OpJump as u8, 0, 0, OpNil as u8, OpReturn as u8], 1)]);
+        assert_eq!(code, [OpNil as u8, OpReturn as u8]);
+    }
+
+    #[test]
+    fn removes_values_that_are_popped_right_away() {
+        let code = optimized(&[
+            (&[OpConstant as u8, 0, OpPop as u8], 1),
+            (&[OpGetLocal as u8, 1, OpPop as u8], 2),
+            (&[OpNil as u8, OpReturn as u8], 3),
+        ]);
+        assert_eq!(code, [OpNil as u8, OpReturn as u8]);
+    }
+
+    #[test]
+    fn removes_assigning_a_local_to_itself() {
+        let code = optimized(&[
+            (&[OpGetLocal as u8, 1, OpSetLocal as u8, 1, OpPop as u8], 1),
+            (&[OpNil as u8, OpReturn as u8], 2),
+        ]);
+        assert_eq!(code, [OpNil as u8, OpReturn as u8]);
+    }
+
+    #[test]
+    fn keeps_stored_values_on_the_stack() {
+        let code = optimized(&[
+            (&[OpSetLocal as u8, 1, OpPop as u8, OpGetLocal as u8, 1], 1),
+            (&[OpReturn as u8], 2),
+        ]);
+        assert_eq!(code, [OpSetLocal as u8, 1, OpReturn as u8]);
+    }
+
+    #[test]
+    fn keeps_pushes_a_jump_lands_between() {
+        let code = [
+            OpTrue as u8,
+            OpJumpIfFalse as u8,
+            0,
+            2,
+            OpNil as u8,
+            OpNil as u8,
+            OpPop as u8,
+            OpReturn as u8,
+        ];
+        // the jump lands on the pop
+        let optimized = optimized(&[(&code, 1)]);
+        assert_eq!(optimized, code);
+    }
+
+    #[test]
+    fn recomputes_jumps_and_ranges() {
+        let optimized = optimize_chunk(
+            chunk(&[
+                (&[OpTrue as u8, OpJumpIfFalse as u8, 0, 4], 1),
+                (&[OpConstant as u8, 0, OpPop as u8], 2),
+                (&[OpNil as u8], 3),
+                (&[OpReturn as u8], 4),
+            ]),
+            &Heap::new(),
+        );
+        assert_eq!(
+            optimized.code_array,
+            [
+                OpTrue as u8,
+                OpJumpIfFalse as u8,
+                0,
+                1,
+                OpNil as u8,
+                OpReturn as u8
+            ]
+        );
+        let ranges = optimized
+            .ranges
+            .iter()
+            .map(|(start, range)| (*start, range.line))
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, [(0, 1), (4, 3), (5, 4)]);
+    }
+
+    #[test]
+    fn narrows_long_jumps() {
+        let narrowed = narrow_jumps(
+            chunk(&[
+                (&[OpJumpLong as u8, 0, 0, 1], 1),
+                (&[OpNil as u8], 2),
+                (&[OpNil as u8, OpLoopLong as u8, 0, 0, 6], 3),
+                (&[OpReturn as u8], 4),
+            ]),
+            &Heap::new(),
+        );
+        let expected = [
+            OpJump as u8,
+            0,
+            1,
+            OpNil as u8,
+            OpNil as u8,
+            OpLoop as u8,
+            0,
+            5,
+            OpReturn as u8,
+        ];
+        assert_eq!(narrowed.code_array, expected);
+        // every range starts one byte earlier after a narrowed jump
+        let ranges = narrowed
+            .ranges
+            .iter()
+            .map(|(start, range)| (*start, range.line))
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, [(0, 1), (3, 2), (4, 3), (8, 4)]);
+    }
+
+    #[test]
+    fn keeps_jumps_long_that_need_it() {
+        let nils = vec![OpNil as u8; 0x1_0000];
+        let narrowed = narrow_jumps(
+            chunk(&[
+                (&[OpJumpLong as u8, 0x01, 0x00, 0x00], 1),
+                (&nils, 1),
+                (&[OpReturn as u8], 1),
+            ]),
+            &Heap::new(),
+        );
+        assert_eq!(
+            narrowed.code_array[..4],
+            [OpJumpLong as u8, 0x01, 0x00, 0x00]
+        );
+        assert_eq!(narrowed.code_array.len(), 4 + nils.len() + 1);
+    }
+}
@@ -1,63 +1,90 @@
-use std::fmt::Display;
+use std::{collections::HashMap, fmt::Display};
 
-use crate::interpreter::parser::ast::Literal;
+use super::{heap::ObjRef, Chunk};
 
-#[derive(Debug, Clone)]
+/// A Lox value. Everything that is not a number, a boolean or `nil` lives on
+/// the [`Heap`](super::Heap) and is referred to by an [`ObjRef`].
+///
+/// Strings are interned, so two values are equal exactly if their handles are.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Value {
     Number(f64),
     Bool(bool),
     Nil,
-    String(String),
+    Obj(ObjRef),
 }
 
-impl PartialEq for Value {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Value::Number(a), Value::Number(b)) => a == b,
-            (Value::Bool(a), Value::Bool(b)) => a == b,
-            (Value::Nil, Value::Nil) => true,
-            (Value::String(a), Value::String(b)) => a == b,
-            _ => false,
-        }
+/// A compiled Lox function. The top-level code is a function without a name.
+pub struct Function {
+    pub(crate) name: String,
+    pub(crate) arity: usize,
+    /// How many variables of enclosing functions the function captures
+    pub(crate) upvalue_count: usize,
+    pub(crate) chunk: Chunk,
+}
+
+impl std::fmt::Debug for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
     }
 }
 
-impl Display for Value {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            Value::Number(n) => write!(f, "{}", n),
-            Value::Bool(b) => write!(f, "{}", b),
-            Value::Nil => write!(f, "nil"),
-            Value::String(s) => write!(f, "{}", s),
+impl Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.name.is_empty() {
+            write!(f, "<script>")
+        } else {
+            write!(f, "<fn {}>", self.name)
         }
     }
 }
 
+/// A function together with the variables it captured when it was declared.
+#[derive(Debug)]
+pub struct Closure {
+    /// Always refers to a [`Function`]
+    pub(crate) function: ObjRef,
+    /// Always refer to [`Upvalue`]s
+    pub(crate) upvalues: Vec<ObjRef>,
+}
+
+/// A captured variable.
+#[derive(Debug)]
+pub enum Upvalue {
+    /// The variable still lives in this stack slot
+    Open(usize),
+    /// The variable went out of scope, so the closures own it now
+    Closed(Value),
+}
+
+#[derive(Debug)]
+pub struct Class {
+    pub(crate) name: String,
+    /// Method closures by their interned name
+    pub(crate) methods: HashMap<ObjRef, ObjRef>,
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub(crate) class: ObjRef,
+    /// Field values by their interned name
+    pub(crate) fields: HashMap<ObjRef, Value>,
+}
+
+/// A method together with the instance `this` refers to in its body.
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub(crate) receiver: Value,
+    pub(crate) method: ObjRef,
+}
+
 impl From<&Value> for bool {
     fn from(v: &Value) -> Self {
         match v {
             Value::Bool(b) => *b,
             Value::Number(n) => *n != 0.0,
             Value::Nil => false,
-            Value::String(s) => !s.is_empty(),
-        }
-    }
-}
-
-#[derive(Debug, Clone)]
-pub struct LiteralToValueError;
-
-impl TryFrom<Literal> for Value {
-    type Error = LiteralToValueError;
-
-    fn try_from(value: Literal) -> Result<Self, Self::Error> {
-        match value {
-            Literal::Number(n) => Ok(Value::Number(n)),
-            Literal::True => Ok(Value::Bool(true)),
-            Literal::False => Ok(Value::Bool(false)),
-            Literal::Nil => Ok(Value::Nil),
-            Literal::String(s) => Ok(Value::String(s)),
-            _ => Err(LiteralToValueError),
+            Value::Obj(_) => true,
         }
     }
 }
@@ -0,0 +1,577 @@
+//! Checks that bytecode is safe to run before the VM trusts it.
+//!
+//! Every instruction of a chunk is decoded and its operands are checked, then
+//! every path through the chunk is followed to track how many values are on
+//! the stack. Afterwards the VM can read operands, constants and locals
+//! without running past the end of anything.
+
+use std::{fmt::Display, rc::Rc};
+
+use super::{Chunk, Function, GlobalTable, Heap, Object, OpCode, Program, Value};
+
+/// How deeply functions may be nested in each other. Loading a `.loxc` file
+/// reads nested functions recursively, so this keeps a crafted file from
+/// overflowing the stack. The compiler refuses to nest functions any deeper.
+pub(crate) const MAX_FUNCTION_NESTING: usize = 256;
+
+#[derive(Debug)]
+pub struct VerifyError {
+    /// The function whose chunk is invalid, `<script>` for the top-level code
+    pub function: String,
+    /// Offset of the offending instruction in the chunk
+    pub offset: usize,
+    pub kind: VerifyErrorKind,
+}
+
+#[derive(Debug)]
+pub enum VerifyErrorKind {
+    InvalidOpcode(u8),
+    /// The chunk ends in the middle of the instruction's operands
+    MissingOperand,
+    InvalidOperand(u8),
+    /// The source ranges don't start at the first byte of code, are out of
+    /// order or go past its end
+    InvalidDebugInfo,
+    ConstantOutOfBounds(usize),
+    /// The constant is of the wrong kind for the instruction
+    InvalidConstant(usize),
+    GlobalOutOfBounds(u16),
+    LocalOutOfBounds(u16),
+    UpvalueOutOfBounds(u16),
+    JumpOutOfBounds(isize),
+    /// The jump lands in the operands of another instruction
+    JumpIntoInstruction(usize),
+    /// The instruction pops more values than the stack holds
+    StackUnderflow,
+    /// Two paths reach the instruction with a different number of values on the stack
+    StackMismatch {
+        expected: usize,
+        found: usize,
+    },
+    /// Execution can run past the last instruction
+    MissingReturn,
+    /// Functions are nested more than [`MAX_FUNCTION_NESTING`] levels deep
+    NestedTooDeep,
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid bytecode in {} at offset {:04}: {}",
+            self.function, self.offset, self.kind
+        )
+    }
+}
+
+impl Display for VerifyErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifyErrorKind::InvalidOpcode(byte) => write!(f, "invalid opcode {}", byte),
+            VerifyErrorKind::MissingOperand => write!(f, "missing operand"),
+            VerifyErrorKind::InvalidOperand(byte) => write!(f, "invalid operand {}", byte),
+            VerifyErrorKind::InvalidDebugInfo => write!(f, "invalid source ranges"),
+            VerifyErrorKind::ConstantOutOfBounds(idx) => {
+                write!(f, "constant {} does not exist", idx)
+            }
+            VerifyErrorKind::InvalidConstant(idx) => {
+                write!(f, "constant {} has the wrong type", idx)
+            }
+            VerifyErrorKind::GlobalOutOfBounds(slot) => write!(f, "global {} does not exist", slot),
+            VerifyErrorKind::LocalOutOfBounds(idx) => write!(f, "local {} does not exist", idx),
+            VerifyErrorKind::UpvalueOutOfBounds(idx) => {
+                write!(f, "upvalue {} does not exist", idx)
+            }
+            VerifyErrorKind::JumpOutOfBounds(target) => {
+                write!(f, "jump to {:04} is outside the chunk", target)
+            }
+            VerifyErrorKind::JumpIntoInstruction(target) => {
+                write!(f, "jump to {:04} lands inside an instruction", target)
+            }
+            VerifyErrorKind::StackUnderflow => write!(f, "stack underflow"),
+            VerifyErrorKind::StackMismatch { expected, found } => write!(
+                f,
+                "reached with {} values on the stack, but also with {}",
+                expected, found
+            ),
+            VerifyErrorKind::MissingReturn => write!(f, "execution runs past the end"),
+            VerifyErrorKind::NestedTooDeep => write!(f, "functions are nested too deeply"),
+        }
+    }
+}
+
+impl Program {
+    /// Checks that the program's code and every function in it are safe to run.
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        let script = Function {
+            name: String::new(),
+            arity: 0,
+            upvalue_count: 0,
+            chunk: Chunk::new(),
+        };
+        // nested functions are checked one after another instead of
+        // recursively, however deep they are nested
+        let mut nested = Vec::new();
+        verify_chunk(
+            &self.chunk,
+            &script,
+            &self.heap,
+            &self.globals,
+            0,
+            &mut nested,
+        )?;
+        while let Some((function, depth)) = nested.pop() {
+            verify_chunk(
+                &function.chunk,
+                &function,
+                &self.heap,
+                &self.globals,
+                depth,
+                &mut nested,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A decoded instruction.
+struct Decoded {
+    offset: usize,
+    op: OpCode,
+    /// How many values it needs on the stack, and how many it leaves there
+    pops: usize,
+    pushes: usize,
+    /// Where a jump goes
+    target: Option<usize>,
+    /// The highest local it refers to, and the stack height the local needs
+    local: Option<(u16, usize)>,
+    /// The function a closure is created from
+    function: Option<Rc<Function>>,
+}
+
+fn verify_chunk(
+    chunk: &Chunk,
+    function: &Function,
+    heap: &Heap,
+    globals: &GlobalTable,
+    // how many functions deep the chunk is, 0 for the top-level code
+    depth: usize,
+    // where the functions created in the chunk are added to be checked next
+    nested: &mut Vec<(Rc<Function>, usize)>,
+) -> Result<(), VerifyError> {
+    let code = &chunk.code_array;
+    let error = |offset, kind| VerifyError {
+        function: function.to_string(),
+        offset,
+        kind,
+    };
+    if depth > MAX_FUNCTION_NESTING {
+        return Err(error(0, VerifyErrorKind::NestedTooDeep));
+    }
+    // every byte of code needs a range, which is looked up by offset
+    let mut next_start = 0;
+    for (idx, &(start, _)) in chunk.ranges.iter().enumerate() {
+        if (idx == 0 && start != 0) || start < next_start || start >= code.len() {
+            return Err(error(start, VerifyErrorKind::InvalidDebugInfo));
+        }
+        next_start = start + 1;
+    }
+    if chunk.ranges.is_empty() && !code.is_empty() {
+        return Err(error(0, VerifyErrorKind::InvalidDebugInfo));
+    }
+
+    // decode every instruction first, so jumps can be checked against the
+    // instruction boundaries
+    let mut instructions = Vec::new();
+    // the index in `instructions` of the instruction starting at every offset
+    let mut starts = vec![None; code.len()];
+    let mut offset = 0;
+    while offset < code.len() {
+        starts[offset] = Some(instructions.len());
+        let (instruction, len) =
+            decode(chunk, offset, function, heap, globals).map_err(|kind| error(offset, kind))?;
+        if let Some(function) = &instruction.function {
+            nested.push((function.clone(), depth + 1));
+        }
+        instructions.push(instruction);
+        offset += len;
+    }
+    for instruction in &instructions {
+        if let Some(target) = instruction.target {
+            if starts[target].is_none() {
+                return Err(error(
+                    instruction.offset,
+                    VerifyErrorKind::JumpIntoInstruction(target),
+                ));
+            }
+        }
+    }
+
+    // then follow every path through the chunk, tracking the stack height
+    // relative to the frame, which starts with the callee and the arguments
+    let mut heights: Vec<Option<usize>> = vec![None; instructions.len()];
+    let mut pending = vec![(0, function.arity + 1)];
+    while let Some((idx, height)) = pending.pop() {
+        let Some(instruction) = instructions.get(idx) else {
+            let last = instructions
+                .last()
+                .map_or(0, |instruction| instruction.offset);
+            return Err(error(last, VerifyErrorKind::MissingReturn));
+        };
+        let offset = instruction.offset;
+        match heights[idx] {
+            Some(expected) if expected != height => {
+                return Err(error(
+                    offset,
+                    VerifyErrorKind::StackMismatch {
+                        expected,
+                        found: height,
+                    },
+                ))
+            }
+            Some(_) => continue,
+            None => heights[idx] = Some(height),
+        }
+        if height < instruction.pops {
+            return Err(error(offset, VerifyErrorKind::StackUnderflow));
+        }
+        if let Some((local, needed)) = instruction.local {
+            if height < needed {
+                return Err(error(offset, VerifyErrorKind::LocalOutOfBounds(local)));
+            }
+        }
+        let height = height - instruction.pops + instruction.pushes;
+        let target = instruction.target.and_then(|target| starts[target]);
+        match (&instruction.op, target) {
+            (OpCode::OpReturn, _) => {}
+            (OpCode::OpJumpIfFalse | OpCode::OpJumpIfFalseLong, Some(target)) => {
+                pending.push((target, height));
+                pending.push((idx + 1, height));
+            }
+            (_, Some(target)) => pending.push((target, height)),
+            (_, None) => pending.push((idx + 1, height)),
+        }
+    }
+    Ok(())
+}
+
+/// Decodes the instruction at `offset` and checks its operands, returning it
+/// and its length.
+fn decode(
+    chunk: &Chunk,
+    offset: usize,
+    function: &Function,
+    heap: &Heap,
+    globals: &GlobalTable,
+) -> Result<(Decoded, usize), VerifyErrorKind> {
+    let code = &chunk.code_array;
+    let operand = |idx: usize| {
+        code.get(offset + 1 + idx)
+            .copied()
+            .ok_or(VerifyErrorKind::MissingOperand)
+    };
+    let operand_u16 = |idx: usize| Ok((operand(idx)? as u16) << 8 | operand(idx + 1)? as u16);
+    let constant = |idx: usize| {
+        chunk
+            .constant_pool
+            .get(idx)
+            .copied()
+            .ok_or(VerifyErrorKind::ConstantOutOfBounds(idx))
+    };
+    let name = |idx: usize| match constant(idx)? {
+        Value::Obj(obj) if matches!(heap.get(obj), Object::String(_)) => Ok(()),
+        _ => Err(VerifyErrorKind::InvalidConstant(idx)),
+    };
+    let upvalue = |idx: u16| {
+        if (idx as usize) < function.upvalue_count {
+            Ok(())
+        } else {
+            Err(VerifyErrorKind::UpvalueOutOfBounds(idx))
+        }
+    };
+
+    let op: OpCode = code[offset]
+        .try_into()
+        .map_err(|_| VerifyErrorKind::InvalidOpcode(code[offset]))?;
+    // the index of the constant the instruction refers to, and its width
+    let constant_operand = || -> Result<(usize, usize), VerifyErrorKind> {
+        if op.is_long_constant() {
+            let idx =
+                (operand(0)? as usize) << 16 | (operand(1)? as usize) << 8 | operand(2)? as usize;
+            Ok((idx, 3))
+        } else {
+            Ok((operand(0)? as usize, 1))
+        }
+    };
+    let mut decoded = Decoded {
+        offset,
+        op,
+        pops: 0,
+        pushes: 0,
+        target: None,
+        local: None,
+        function: None,
+    };
+    use OpCode::*;
+    let (len, pops, pushes) = match decoded.op {
+        OpReturn | OpPrint | OpPop | OpCloseUpvalue => (1, 1, 0),
+        // the superclass stays below the subclass
+        OpInherit => (1, 2, 1),
+        OpNil | OpTrue | OpFalse => (1, 0, 1),
+        OpNot | OpNegate => (1, 1, 1),
+        OpEq | OpGreater | OpLess | OpAdd | OpSubtract | OpMultiply | OpDivide | OpGreaterEqual
+        | OpLessEqual | OpNotEqual => (1, 2, 1),
+        OpConstant => {
+            constant(operand(0)? as usize)?;
+            (2, 0, 1)
+        }
+        OpConstantLong => {
+            constant(constant_operand()?.0)?;
+            (4, 0, 1)
+        }
+        OpDefineGlobal | OpGetGlobal | OpSetGlobal => {
+            let slot = operand_u16(0)?;
+            if slot as usize >= globals.len() {
+                return Err(VerifyErrorKind::GlobalOutOfBounds(slot));
+            }
+            match decoded.op {
+                OpDefineGlobal => (3, 1, 0),
+                OpGetGlobal => (3, 0, 1),
+                _ => (3, 1, 1),
+            }
+        }
+        OpGetLocal | OpSetLocal => {
+            let idx = operand(0)? as u16;
+            decoded.local = Some((idx, idx as usize + 1));
+            match decoded.op {
+                OpGetLocal => (2, 0, 1),
+                _ => (2, 1, 1),
+            }
+        }
+        OpGetLocalLong | OpSetLocalLong => {
+            let idx = operand_u16(0)?;
+            decoded.local = Some((idx, idx as usize + 1));
+            match decoded.op {
+                OpGetLocalLong => (3, 0, 1),
+                _ => (3, 1, 1),
+            }
+        }
+        OpGetUpvalue => {
+            upvalue(operand(0)? as u16)?;
+            (2, 0, 1)
+        }
+        OpSetUpvalue => {
+            upvalue(operand(0)? as u16)?;
+            (2, 1, 1)
+        }
+        OpJump | OpJumpIfFalse | OpLoop | OpJumpLong | OpJumpIfFalseLong | OpLoopLong => {
+            let (len, jump) = match decoded.op {
+                OpJump | OpJumpIfFalse | OpLoop => (3, operand_u16(0)? as isize),
+                _ => (4, (operand(0)? as isize) << 16 | (operand_u16(1)? as isize)),
+            };
+            let target = (offset + len) as isize
+                + if matches!(decoded.op, OpLoop | OpLoopLong) {
+                    -jump
+                } else {
+                    jump
+                };
+            if target < 0 || target as usize >= code.len() {
+                return Err(VerifyErrorKind::JumpOutOfBounds(target));
+            }
+            decoded.target = Some(target as usize);
+            match decoded.op {
+                // the condition stays on the stack either way
+                OpJumpIfFalse | OpJumpIfFalseLong => (len, 1, 1),
+                _ => (len, 0, 0),
+            }
+        }
+        OpCall => {
+            let arg_count = operand(0)? as usize;
+            (2, arg_count + 1, 1)
+        }
+        OpClosure | OpClosureLong => {
+            let (idx, width) = constant_operand()?;
+            let closure = match constant(idx)? {
+                Value::Obj(obj) => match heap.get(obj) {
+                    Object::Function(closure) => closure.clone(),
+                    _ => return Err(VerifyErrorKind::InvalidConstant(idx)),
+                },
+                _ => return Err(VerifyErrorKind::InvalidConstant(idx)),
+            };
+            for i in 0..closure.upvalue_count {
+                let is_local = operand(width + 3 * i)?;
+                let idx = operand_u16(width + 3 * i + 1)?;
+                match is_local {
+                    // a local function captures itself, in the slot the
+                    // closure is pushed to
+                    1 if decoded.local.map_or(true, |(local, _)| local < idx) => {
+                        decoded.local = Some((idx, idx as usize));
+                    }
+                    1 => {}
+                    0 => upvalue(idx)?,
+                    _ => return Err(VerifyErrorKind::InvalidOperand(is_local)),
+                }
+            }
+            decoded.function = Some(closure.clone());
+            (1 + width + 3 * closure.upvalue_count, 0, 1)
+        }
+        OpClass | OpClassLong => {
+            let (idx, width) = constant_operand()?;
+            name(idx)?;
+            (1 + width, 0, 1)
+        }
+        OpGetProperty | OpGetPropertyLong => {
+            let (idx, width) = constant_operand()?;
+            name(idx)?;
+            (1 + width, 1, 1)
+        }
+        OpMethod | OpSetProperty | OpGetSuper | OpMethodLong | OpSetPropertyLong
+        | OpGetSuperLong => {
+            let (idx, width) = constant_operand()?;
+            name(idx)?;
+            (1 + width, 2, 1)
+        }
+        OpInvoke | OpSuperInvoke | OpInvokeLong | OpSuperInvokeLong => {
+            let (idx, width) = constant_operand()?;
+            name(idx)?;
+            let arg_count = operand(width)? as usize;
+            // the receiver, and the superclass on top of the arguments
+            match decoded.op {
+                OpInvoke | OpInvokeLong => (2 + width, arg_count + 1, 1),
+                _ => (2 + width, arg_count + 2, 1),
+            }
+        }
+    };
+    decoded.pops = pops;
+    decoded.pushes = pushes;
+    Ok((decoded, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::SourceCodeRange;
+    use OpCode::*;
+
+    fn chunk(code: &[u8], constants: Vec<Value>) -> Chunk {
+        let mut chunk = Chunk::new();
+        for &byte in code {
+            chunk.push_code(byte, SourceCodeRange::new(1));
+        }
+        chunk.constant_pool = constants;
+        chunk
+    }
+
+    fn verify(code: &[u8], constants: Vec<Value>) -> Result<(), VerifyError> {
+        Program {
+            chunk: chunk(code, constants),
+            heap: Heap::new(),
+            globals: GlobalTable::new(),
+        }
+        .verify()
+    }
+
+    /// A program whose functions are nested `depth` levels deep.
+    pub(crate) fn nested_program(depth: usize) -> Program {
+        let mut heap = Heap::new();
+        let mut code = chunk(&[OpNil as u8, OpReturn as u8], Vec::new());
+        for level in (1..=depth).rev() {
+            let function = heap.alloc(Object::Function(Rc::new(Function {
+                name: format!("level{}", level),
+                arity: 0,
+                upvalue_count: 0,
+                chunk: code,
+            })));
+            code = chunk(
+                &[OpClosure as u8, 0, OpReturn as u8],
+                vec![Value::Obj(function)],
+            );
+        }
+        Program {
+            chunk: code,
+            heap,
+            globals: GlobalTable::new(),
+        }
+    }
+
+    #[test]
+    fn accepts_valid_code() {
+        let code = [
+            OpConstant as u8,
+            0,
+            OpPrint as u8,
+            OpNil as u8,
+            OpReturn as u8,
+        ];
+        verify(&code, vec![Value::Number(1.0)]).unwrap();
+    }
+
+    #[test]
+    fn rejects_missing_constant() {
+        let code = [OpConstant as u8, 1, OpReturn as u8];
+        let err = verify(&code, vec![Value::Number(1.0)]).unwrap_err();
+        assert_eq!(err.offset, 0);
+        assert!(matches!(err.kind, VerifyErrorKind::ConstantOutOfBounds(1)));
+    }
+
+    #[test]
+    fn rejects_jump_into_operands() {
+        // the jump lands on the operand of the constant
+        let code = [OpJump as u8, 0, 1, OpConstant as u8, 0, OpReturn as u8];
+        let err = verify(&code, vec![Value::Number(1.0)]).unwrap_err();
+        assert_eq!(err.offset, 0);
+        assert!(matches!(err.kind, VerifyErrorKind::JumpIntoInstruction(4)));
+    }
+
+    #[test]
+    fn rejects_different_stack_heights() {
+        // skipping the `nil` leaves one value less on the stack at the return
+        let code = [
+            OpTrue as u8,
+            OpJumpIfFalse as u8,
+            0,
+            1,
+            OpNil as u8,
+            OpReturn as u8,
+        ];
+        let err = verify(&code, Vec::new()).unwrap_err();
+        assert_eq!(err.offset, 5);
+        assert!(matches!(
+            err.kind,
+            VerifyErrorKind::StackMismatch {
+                expected: 3,
+                found: 2
+            }
+        ));
+    }
+
+    #[test]
+    fn rejects_missing_local() {
+        // only the script itself is in slot 0
+        let code = [OpGetLocal as u8, 1, OpReturn as u8];
+        let err = verify(&code, Vec::new()).unwrap_err();
+        assert_eq!(err.offset, 0);
+        assert!(matches!(err.kind, VerifyErrorKind::LocalOutOfBounds(1)));
+    }
+
+    #[test]
+    fn rejects_missing_return() {
+        let code = [OpNil as u8, OpPop as u8];
+        let err = verify(&code, Vec::new()).unwrap_err();
+        assert_eq!(err.offset, 1);
+        assert!(matches!(err.kind, VerifyErrorKind::MissingReturn));
+    }
+
+    #[test]
+    fn limits_function_nesting() {
+        nested_program(MAX_FUNCTION_NESTING).verify().unwrap();
+        let err = nested_program(MAX_FUNCTION_NESTING + 1)
+            .verify()
+            .unwrap_err();
+        assert_eq!(
+            err.function,
+            format!("<fn level{}>", MAX_FUNCTION_NESTING + 1)
+        );
+        assert!(matches!(err.kind, VerifyErrorKind::NestedTooDeep));
+    }
+}
@@ -0,0 +1,229 @@
+//! Golden-file tests: Lox scripts that state what running them should do in
+//! comments, in the style of the Crafting Interpreters test suite.
+//!
+//! - `// expect: <text>` after a statement that prints `<text>` on a line of
+//!   its own
+//! - `// expect runtime error: <message>` on the line a runtime error is
+//!   reported on
+//! - `// [line N] Error<...>: <message>` for an error reported before the
+//!   script runs, e.g. by the parser. Without `[line N]` the error is
+//!   expected on the line of the comment itself.
+//!
+//! Every script runs on both backends, and has to behave as stated on each.
+//!
+//! ```
+//! use rlox::{backend::Backend, golden};
+//!
+//! let source = "print 1 + 2; // expect: 3\nprint -nil; // expect runtime error: Unary minus expects a number";
+//! assert!(golden::check(source, Backend::Tree, false, false).is_empty());
+//! assert!(golden::check(source, Backend::Vm, false, false).is_empty());
+//! ```
+
+use std::{
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    backend::{self, Backend, ReportedError},
+    interpreter::{lexer, parser::ParserInstance},
+};
+
+const EXPECT_OUTPUT: &str = "// expect: ";
+const EXPECT_RUNTIME_ERROR: &str = "// expect runtime error: ";
+
+/// What a golden script says about itself.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Expectations {
+    /// The lines the script prints, in order
+    pub output: Vec<String>,
+    /// The runtime error the script stops with
+    pub runtime_error: Option<ReportedError>,
+    /// The errors reported before the script runs
+    pub compile_errors: Vec<ReportedError>,
+    /// The exit status `rlox run` ends the script with
+    pub status: i32,
+}
+
+impl Expectations {
+    /// Collects the annotations in `source`.
+    pub fn parse(source: &str) -> Self {
+        let mut expectations = Self::default();
+        for (idx, line) in source.lines().enumerate() {
+            let line_number = idx + 1;
+            if let Some((_, text)) = line.split_once(EXPECT_OUTPUT) {
+                expectations.output.push(text.to_string());
+            } else if let Some((_, message)) = line.split_once(EXPECT_RUNTIME_ERROR) {
+                expectations.runtime_error = Some(ReportedError {
+                    line: Some(line_number),
+                    message: message.to_string(),
+                });
+            } else if let Some(error) = compile_error(line, line_number) {
+                expectations.compile_errors.push(error);
+            }
+        }
+        expectations.status = if !expectations.compile_errors.is_empty() {
+            // errors in a script that parses are about the scope of names
+            if parses(source) {
+                75
+            } else {
+                65
+            }
+        } else if expectations.runtime_error.is_some() {
+            70
+        } else {
+            0
+        };
+        expectations
+    }
+}
+
+/// Whether `source` gets past the lexer and the parser.
+fn parses(source: &str) -> bool {
+    let (tokens, lex_errors) = lexer::tokenize(source);
+    lex_errors.is_empty() && ParserInstance::new(tokens).parse().is_ok()
+}
+
+/// Parses a `// [line N] Error...: <message>` or `// Error...: <message>`
+/// annotation on the line `line_number`.
+fn compile_error(line: &str, line_number: usize) -> Option<ReportedError> {
+    let (_, comment) = line.split_once("// ")?;
+    let (line, comment) = match comment.strip_prefix("[line ") {
+        Some(rest) => {
+            let (line, rest) = rest.split_once("] ")?;
+            (line.parse().ok()?, rest)
+        }
+        None => (line_number, comment),
+    };
+    // what the error is reported at doesn't have to be spelled out
+    let (_, message) = comment.strip_prefix("Error")?.split_once(": ")?;
+    Some(ReportedError {
+        line: Some(line),
+        message: message.to_string(),
+    })
+}
+
+/// Runs `source` on `backend` and describes every way it deviates from its
+/// annotations. The script passed if nothing is returned. `optimize` and
+/// `stress_gc` are passed on to [`backend::run`].
+pub fn check(source: &str, backend: Backend, optimize: bool, stress_gc: bool) -> Vec<String> {
+    let expected = Expectations::parse(source);
+    let outcome = backend::run(backend, source, optimize, stress_gc);
+    let mut failures = Vec::new();
+
+    let output = outcome.output.lines().collect::<Vec<_>>();
+    let matching = output
+        .iter()
+        .zip(&expected.output)
+        .take_while(|(actual, expected)| actual == expected)
+        .count();
+    match (output.get(matching), expected.output.get(matching)) {
+        (None, None) => {}
+        (Some(actual), Some(expected)) => failures.push(format!(
+            "output line {}: expected {:?}, got {:?}",
+            matching + 1,
+            expected,
+            actual
+        )),
+        (Some(actual), None) => failures.push(format!(
+            "output line {}: expected nothing, got {:?}",
+            matching + 1,
+            actual
+        )),
+        (None, Some(expected)) => failures.push(format!(
+            "output line {}: expected {:?}, got nothing",
+            matching + 1,
+            expected
+        )),
+    }
+
+    let expected_errors = match &expected.runtime_error {
+        Some(error) => vec![error.clone()],
+        None => expected.compile_errors.clone(),
+    };
+    for error in &expected_errors {
+        if !outcome.errors.contains(error) {
+            failures.push(format!("missing error: {}", describe(error)));
+        }
+    }
+    for error in &outcome.errors {
+        if !expected_errors.contains(error) {
+            failures.push(format!("unexpected error: {}", describe(error)));
+        }
+    }
+
+    if outcome.status != expected.status {
+        failures.push(format!(
+            "expected exit status {}, got {}",
+            expected.status, outcome.status
+        ));
+    }
+    failures
+}
+
+fn describe(error: &ReportedError) -> String {
+    match error.line {
+        Some(line) => format!("[line {}] {}", line, error.message),
+        None => error.message.clone(),
+    }
+}
+
+/// The result of running one script on one backend.
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub path: PathBuf,
+    pub backend: Backend,
+    /// How the script deviated from its annotations, empty if it passed
+    pub failures: Vec<String>,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl Display for TestResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let verdict = if self.passed() { "PASS" } else { "FAIL" };
+        write!(f, "{} {} [{}]", verdict, self.path.display(), self.backend)?;
+        for failure in &self.failures {
+            write!(f, "\n    {}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs every `.lox` file below `dir` on both backends, in the order of
+/// their paths.
+pub fn run_dir(dir: &Path, optimize: bool, stress_gc: bool) -> io::Result<Vec<TestResult>> {
+    let mut scripts = Vec::new();
+    find_scripts(dir, &mut scripts)?;
+    scripts.sort();
+
+    let mut results = Vec::new();
+    for path in scripts {
+        let source = fs::read_to_string(&path)?;
+        for backend in [Backend::Tree, Backend::Vm] {
+            results.push(TestResult {
+                path: path.clone(),
+                backend,
+                failures: check(&source, backend, optimize, stress_gc),
+            });
+        }
+    }
+    Ok(results)
+}
+
+fn find_scripts(dir: &Path, scripts: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_scripts(&path, scripts)?;
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            scripts.push(path);
+        }
+    }
+    Ok(())
+}
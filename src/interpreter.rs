@@ -1,30 +1,50 @@
+pub mod diagnostic;
 mod eval;
+pub mod fold;
 pub mod lexer;
 pub mod parser;
 pub mod resolver;
 
 use std::fmt::Display;
 
-pub(crate) use lexer::token;
+use crate::{
+    natives::{Arity, NativeArgs, NativeFunction, NativeValue, Natives},
+    output::Output,
+};
+use diagnostic::Diagnostic;
+use eval::Eval;
+pub use lexer::token;
 use lexer::tokenize;
+pub use eval::{ExecError, StackFrame};
+pub use parser::ast::Literal;
 use parser::ast::{Expr, Stmt};
 
-#[derive(Debug, Clone, Copy)]
-pub(crate) struct SourceCodeRange {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceCodeRange {
     pub(crate) line: usize,
     pub(crate) start_column: usize,
     pub(crate) length: usize,
 }
 
 impl SourceCodeRange {
+    /// Returns a range covering both `self` and `other`.
+    ///
+    /// Ranges only describe a single line, so if the two ranges are on different
+    /// lines the result is the range that comes first.
     pub(crate) fn merge(&self, other: &Self) -> Self {
-        let line = self.line.min(other.line);
+        if self.line != other.line {
+            return if self.line < other.line {
+                *self
+            } else {
+                *other
+            };
+        }
         let start_column = self.start_column.min(other.start_column);
-        let length = self.length + other.length;
+        let end_column = (self.start_column + self.length).max(other.start_column + other.length);
         Self {
-            line,
+            line: self.line,
             start_column,
-            length,
+            length: end_column - start_column,
         }
     }
 
@@ -38,37 +58,85 @@ impl SourceCodeRange {
 }
 
 #[derive(Debug)]
-pub(crate) enum InterpreterError {
-    LexError,
-    ParseError(()),
+pub enum InterpreterError {
+    LexError(Vec<Diagnostic>),
+    ParseError(Vec<Diagnostic>),
     ResolverError(resolver::ResolverError),
-    ExecError(()),
+    ExecError(eval::ExecError),
 }
 
-impl Display for InterpreterError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl InterpreterError {
+    /// The exit status `rlox run` ends with: 65 for errors in the syntax, 75
+    /// for scope errors the resolver finds and 70 for runtime errors.
+    pub fn status(&self) -> i32 {
+        match self {
+            InterpreterError::LexError(_) | InterpreterError::ParseError(_) => 65,
+            InterpreterError::ResolverError(_) => 75,
+            InterpreterError::ExecError(_) => 70,
+        }
+    }
+
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
         match self {
-            InterpreterError::LexError => write!(f, "Lex error"),
-            InterpreterError::ResolverError(err) => write!(f, "Resolver error: {:?}", err),
-            InterpreterError::ParseError(_) => write!(f, "Parse error"),
-            InterpreterError::ExecError(_) => write!(f, "Exec error"),
+            InterpreterError::LexError(diagnostics) | InterpreterError::ParseError(diagnostics) => {
+                diagnostics.clone()
+            }
+            InterpreterError::ResolverError(err) => vec![err.into()],
+            InterpreterError::ExecError(err) => vec![err.into()],
         }
     }
 }
 
+impl Display for InterpreterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let diagnostics = self
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect::<Vec<_>>();
+        write!(f, "{}", diagnostics.join("\n"))
+    }
+}
+
+/// Turns the diagnostics of the lexer and the parser into a single error.
+///
+/// Lexer errors take precedence, but the parser still gets to run so that
+/// all problems in the input are reported at once.
+fn parse_error(lex_errors: Vec<Diagnostic>, parse_errors: Vec<Diagnostic>) -> InterpreterError {
+    if lex_errors.is_empty() {
+        InterpreterError::ParseError(parse_errors)
+    } else {
+        let mut diagnostics = lex_errors;
+        diagnostics.extend(parse_errors);
+        InterpreterError::LexError(diagnostics)
+    }
+}
+
 pub fn parse(input: &str) -> Result<Vec<Stmt>, InterpreterError> {
-    let tokens = tokenize(input).map_err(|_| InterpreterError::LexError)?;
+    parse_from(input, 1).map(|(stmts, _)| stmts)
+}
 
-    let mut parser = parser::ParserInstance::new(tokens);
-    parser.parse().map_err(|_| InterpreterError::ParseError(()))
+/// Parses `input`, numbering expressions from `first_expr_id` on. Also returns
+/// the first id that is still unused.
+fn parse_from(input: &str, first_expr_id: usize) -> Result<(Vec<Stmt>, usize), InterpreterError> {
+    let (tokens, lex_errors) = tokenize(input);
+
+    let mut parser = parser::ParserInstance::new(tokens).with_first_expr_id(first_expr_id);
+    match parser.parse() {
+        Ok(stmts) if lex_errors.is_empty() => Ok((stmts, parser.next_expr_id())),
+        Ok(_) => Err(parse_error(lex_errors, Vec::new())),
+        Err(parse_errors) => Err(parse_error(lex_errors, parse_errors)),
+    }
 }
 
 pub fn parse_expr(input: &str) -> Result<Expr, InterpreterError> {
-    let tokens = tokenize(input).map_err(|_| InterpreterError::LexError)?;
+    let (tokens, lex_errors) = tokenize(input);
     let mut parser = parser::ParserInstance::new(tokens);
-    parser
-        .parse_expr()
-        .map_err(|_| InterpreterError::ParseError(()))
+    match parser.parse_expr() {
+        Ok(expr) if lex_errors.is_empty() => Ok(expr),
+        Ok(_) => Err(parse_error(lex_errors, Vec::new())),
+        Err(parse_errors) => Err(parse_error(lex_errors, parse_errors)),
+    }
 }
 
 pub fn eval(input: &str) -> Result<parser::ast::Literal, InterpreterError> {
@@ -78,26 +146,199 @@ pub fn eval(input: &str) -> Result<parser::ast::Literal, InterpreterError> {
     resolver
         .resolve_expr(&expr)
         .map_err(InterpreterError::ResolverError)?;
+    let expr = fold::fold_expr(expr);
 
-    let mut ctx = eval::EvalCtx::new_globals(resolver.into_resolved_exprs());
-    eval::Eval::eval(&expr, &mut ctx).map_err(|_| InterpreterError::ExecError(()))
+    let mut ctx = eval::EvalCtx::new_globals(resolver.into_resolved_exprs(), &Natives::default());
+    eval::Eval::eval(&expr, &mut ctx).map_err(InterpreterError::ExecError)
 }
 
 pub fn run(input: &str) -> Result<(), InterpreterError> {
-    let stmts = parse(input).map_err(|_| InterpreterError::ParseError(()))?;
+    Interpreter::new().run(input)
+}
 
-    let mut resolver = resolver::Resolver::new();
-    resolver
-        .resolve(&stmts)
-        .map_err(InterpreterError::ResolverError)?;
+/// A tree-walking interpreter that keeps its globals alive between programs.
+///
+/// ```
+/// use rlox::interpreter::{Interpreter, Literal};
+///
+/// let mut lox = Interpreter::new();
+/// lox.run("fun add(a, b) { return a + b; }").unwrap();
+/// lox.set_global("x", Literal::Number(40.0));
+/// lox.run("var y = add(x, 2);").unwrap();
+/// assert_eq!(lox.get_global("y"), Some(Literal::Number(42.0)));
+///
+/// let sum = lox.call("add", vec![Literal::Number(1.0), Literal::Number(2.0)]);
+/// assert_eq!(sum.unwrap(), Literal::Number(3.0));
+/// ```
+pub struct Interpreter {
+    resolver: resolver::Resolver,
+    ctx: eval::EvalCtx,
+    /// Expression ids have to be unique across all programs run by this interpreter
+    next_expr_id: usize,
+    /// Where errors are reported to, if anywhere, in addition to being returned
+    diagnostics: Option<Output>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    /// Creates an interpreter with the standard native functions.
+    pub fn new() -> Self {
+        Self::with_natives(&Natives::default())
+    }
+
+    /// Creates an interpreter whose only native functions are `natives`.
+    pub fn with_natives(natives: &Natives) -> Self {
+        Self {
+            resolver: resolver::Resolver::new(),
+            ctx: eval::EvalCtx::new_globals(Default::default(), natives),
+            next_expr_id: 1,
+            diagnostics: None,
+        }
+    }
+
+    /// Makes a Rust function callable from Lox under `name`.
+    ///
+    /// ```
+    /// use rlox::{natives::Arity, Interpreter};
+    ///
+    /// let mut lox = Interpreter::new();
+    /// lox.register_native("double", Arity::Exact(1), |args| Ok(args.get::<f64>(0)? * 2.0));
+    /// lox.run("print double(21);").unwrap();
+    /// ```
+    pub fn register_native<R: Into<NativeValue>>(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        function: impl Fn(&NativeArgs) -> Result<R, String> + 'static,
+    ) {
+        self.ctx
+            .define_native(NativeFunction::new(name, arity, function));
+    }
+
+    /// Limits how deeply Lox calls may be nested. Deeper calls fail with a
+    /// "Stack overflow." error instead of crashing the host.
+    pub fn set_max_call_depth(&mut self, max_depth: usize) {
+        self.ctx.set_max_call_depth(max_depth);
+    }
+
+    /// Sends the output of `print` statements to `output` instead of stdout.
+    pub fn set_output(&mut self, output: Output) {
+        self.ctx.set_output(output);
+    }
+
+    /// Renders the diagnostics of every failing [`Interpreter::run`] to `output`.
+    ///
+    /// The error is still returned as well.
+    pub fn set_diagnostic_output(&mut self, output: Output) {
+        self.diagnostics = Some(output);
+    }
+
+    /// Runs a program. Globals it defines stay available to later programs.
+    pub fn run(&mut self, input: &str) -> Result<(), InterpreterError> {
+        let result = self.run_program(input);
+        self.report(&result, input);
+        result.map(|_| ())
+    }
+
+    /// Like [`Interpreter::run`], but returns the value of the program's last
+    /// statement if it is an expression statement, as a REPL would echo it.
+    pub fn run_interactive(&mut self, input: &str) -> Result<Option<Literal>, InterpreterError> {
+        let result = self.run_program(input);
+        self.report(&result, input);
+        result
+    }
+
+    fn report<T>(&self, result: &Result<T, InterpreterError>, input: &str) {
+        if let (Err(err), Some(output)) = (result, &self.diagnostics) {
+            for diagnostic in err.diagnostics() {
+                // a broken diagnostics sink must not hide the original error
+                let _ = output.write_str(&diagnostic.render(input));
+            }
+        }
+    }
+
+    fn run_program(&mut self, input: &str) -> Result<Option<Literal>, InterpreterError> {
+        let (stmts, next_expr_id) = parse_from(input, self.next_expr_id)?;
+        self.next_expr_id = next_expr_id;
+
+        // resolved before folding, so that code which can never run is still
+        // checked. Folding keeps the ids of the expressions and the scopes
+        let resolved = self.resolver.resolve(&stmts);
+        self.ctx.add_locals(self.resolver.take_resolved_exprs());
+        if let Err(err) = resolved {
+            // the failed program may have left scopes open
+            self.resolver.recover();
+            return Err(InterpreterError::ResolverError(err));
+        }
+        let stmts = fold::fold(stmts);
+
+        let mut last_value = None;
+        for stmt in &stmts {
+            last_value = match &stmt.intern {
+                parser::ast::StmtType::Expr(expr) => Some(
+                    expr.eval(&mut self.ctx)
+                        .map_err(InterpreterError::ExecError)?,
+                ),
+                _ => {
+                    stmt.eval(&mut self.ctx)
+                        .map_err(InterpreterError::ExecError)?;
+                    None
+                }
+            };
+        }
+        Ok(last_value)
+    }
+
+    /// Returns all globals defined by Lox code or [`Interpreter::set_global`],
+    /// sorted by name. Native functions are not included.
+    pub fn globals(&self) -> Vec<(String, Literal)> {
+        self.ctx.top_level_variables()
+    }
+
+    /// Returns the value of a global variable or native function.
+    pub fn get_global(&self, name: &str) -> Option<Literal> {
+        self.ctx.get_global(name)
+    }
+
+    /// Defines or overwrites a global variable.
+    pub fn set_global(&mut self, name: &str, value: Literal) {
+        self.resolver.define_global(name);
+        self.ctx.insert(name.to_string(), value);
+    }
 
-    let mut ctx = eval::EvalCtx::new_globals(resolver.into_resolved_exprs());
-    for stmt in &stmts {
-        let result = stmt.eval(&mut ctx);
-        if let Err(err) = result {
-            eprintln!("ExecError: {}", err);
-            return Err(InterpreterError::ExecError(()));
+    /// Calls the global function or class `name` with `args`.
+    ///
+    /// There is no call site in Lox code, so errors about the call itself, like
+    /// a wrong number of arguments, point at the declaration of `name`. Errors
+    /// for names that weren't declared in Lox code have no range, like errors
+    /// in native functions.
+    ///
+    /// ```
+    /// use rlox::Interpreter;
+    ///
+    /// let mut lox = Interpreter::new();
+    /// lox.run("var x = 1;\nfun add(a, b) { return a + b; }").unwrap();
+    /// let err = lox.call("add", vec![]).unwrap_err();
+    /// assert!(err.to_string().starts_with("[line 2]"));
+    /// ```
+    pub fn call(&mut self, name: &str, args: Vec<Literal>) -> Result<Literal, InterpreterError> {
+        let callee = self.get_global(name).ok_or_else(|| {
+            InterpreterError::ExecError(eval::ExecError::new(
+                format!("Undefined function '{}'", name),
+                SourceCodeRange::new(0),
+            ))
+        })?;
+        let range = match &callee {
+            Literal::Callable(callable) => callable.range(),
+            Literal::Class(class) => Some(class.range),
+            _ => None,
         }
+        .unwrap_or(SourceCodeRange::new(0));
+        eval::call_value(callee, args, &mut self.ctx, range).map_err(InterpreterError::ExecError)
     }
-    Ok(())
 }
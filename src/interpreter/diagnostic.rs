@@ -0,0 +1,180 @@
+use std::fmt::{Display, Write};
+
+use super::SourceCodeRange;
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A secondary source location that adds context to a [`Diagnostic`].
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub(crate) range: SourceCodeRange,
+    pub(crate) message: String,
+}
+
+/// A single problem found in a Lox program, produced by any phase from
+/// lexing to evaluation.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub(crate) severity: Severity,
+    pub(crate) code: &'static str,
+    pub(crate) message: String,
+    pub(crate) range: SourceCodeRange,
+    pub(crate) labels: Vec<Label>,
+    pub(crate) notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub(crate) fn error(code: &'static str, message: String, range: SourceCodeRange) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            message,
+            range,
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub(crate) fn with_label(mut self, range: SourceCodeRange, message: String) -> Self {
+        self.labels.push(Label { range, message });
+        self
+    }
+
+    pub(crate) fn with_note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    /// Renders the diagnostic together with the offending source lines,
+    /// underlining the primary range with `^` and labels with `-`.
+    pub fn render(&self, source: &str) -> String {
+        let lines = source.lines().collect::<Vec<_>>();
+        let gutter = std::iter::once(&self.range)
+            .chain(self.labels.iter().map(|label| &label.range))
+            .map(|range| range.line.to_string().len())
+            .max()
+            .unwrap_or(1);
+
+        let mut out = String::new();
+        writeln!(out, "{}[{}]: {}", self.severity, self.code, self.message).unwrap();
+        writeln!(
+            out,
+            "{:gutter$}--> line {}:{}",
+            "", self.range.line, self.range.start_column
+        )
+        .unwrap();
+        writeln!(out, "{:gutter$} |", "").unwrap();
+        render_snippet(&mut out, &lines, &self.range, '^', "", gutter);
+        for label in &self.labels {
+            render_snippet(&mut out, &lines, &label.range, '-', &label.message, gutter);
+        }
+        for note in &self.notes {
+            writeln!(out, "{:gutter$} = note: {}", "", note).unwrap();
+        }
+        out
+    }
+
+    /// Serializes the diagnostic as a single-line JSON object.
+    pub fn to_json(&self) -> String {
+        let labels = self
+            .labels
+            .iter()
+            .map(|label| {
+                format!(
+                    "{{\"range\":{},\"message\":{}}}",
+                    range_to_json(&label.range),
+                    json_string(&label.message)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let notes = self
+            .notes
+            .iter()
+            .map(|note| json_string(note))
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            "{{\"severity\":\"{}\",\"code\":\"{}\",\"message\":{},\"range\":{},\"labels\":[{}],\"notes\":[{}]}}",
+            self.severity,
+            self.code,
+            json_string(&self.message),
+            range_to_json(&self.range),
+            labels,
+            notes
+        )
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[line {}] {}[{}]: {}",
+            self.range.line, self.severity, self.code, self.message
+        )
+    }
+}
+
+fn render_snippet(
+    out: &mut String,
+    lines: &[&str],
+    range: &SourceCodeRange,
+    underline: char,
+    message: &str,
+    gutter: usize,
+) {
+    let Some(text) = range.line.checked_sub(1).and_then(|idx| lines.get(idx)) else {
+        return;
+    };
+    writeln!(out, "{:>gutter$} | {}", range.line, text).unwrap();
+
+    let line_length = text.chars().count();
+    let start = range.start_column.saturating_sub(1).min(line_length);
+    let length = range.length.max(1).min(line_length.saturating_sub(start).max(1));
+    let marker = underline.to_string().repeat(length);
+    let mut marker_line = format!("{:gutter$} | {}{}", "", " ".repeat(start), marker);
+    if !message.is_empty() {
+        write!(marker_line, " {}", message).unwrap();
+    }
+    writeln!(out, "{}", marker_line).unwrap();
+}
+
+fn range_to_json(range: &SourceCodeRange) -> String {
+    format!(
+        "{{\"line\":{},\"column\":{},\"length\":{}}}",
+        range.line, range.start_column, range.length
+    )
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
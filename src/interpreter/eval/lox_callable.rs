@@ -1,21 +1,24 @@
-use std::backtrace::Backtrace;
-use std::usize;
-
 use crate::interpreter::eval::ExecError;
+use crate::interpreter::SourceCodeRange;
+use crate::natives::{NativeFunction, NativeValue};
 
 use crate::interpreter::eval::EvalCtx;
 
 use super::Literal;
 
-pub(crate) trait LoxCallable:
-    LoxCallableClone + std::fmt::Debug + std::fmt::Display
-{
+pub trait LoxCallable: LoxCallableClone + std::fmt::Debug + std::fmt::Display {
     fn call(&self, args: Vec<Literal>, ctx: &mut EvalCtx) -> Result<Literal, ExecError>;
     fn arity_matches(&self, arity: usize) -> bool;
     fn print_arity(&self) -> String;
+    /// The name used to refer to this callable in error messages.
+    fn name(&self) -> String;
+    /// Where the callable is declared, if it was declared in Lox code.
+    fn range(&self) -> Option<SourceCodeRange> {
+        None
+    }
 }
 
-pub(crate) trait LoxCallableClone {
+pub trait LoxCallableClone {
     fn clone_box(&self) -> Box<dyn LoxCallable>;
 }
 
@@ -40,110 +43,53 @@ impl PartialEq for Box<dyn LoxCallable> {
     }
 }
 
-#[derive(Clone)]
-pub(crate) struct Clock;
-
-impl LoxCallable for Clock {
-    fn call(&self, _args: Vec<Literal>, _ctx: &mut EvalCtx) -> Result<Literal, ExecError> {
-        Ok(Literal::Number(
-            std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs_f64(),
-        ))
-    }
-
-    fn arity_matches(&self, arity: usize) -> bool {
-        (0..).contains(&arity)
-    }
-    
-    fn print_arity(&self) -> String {
-        "0".to_string()
-    }
-}
-
-impl std::fmt::Display for Clock {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<native clock fn>")
+impl From<NativeValue> for Literal {
+    fn from(value: NativeValue) -> Self {
+        match value {
+            NativeValue::Nil => Literal::Nil,
+            NativeValue::Bool(b) => Literal::from(b),
+            NativeValue::Number(n) => Literal::Number(n),
+            NativeValue::String(s) => Literal::String(s),
+        }
     }
 }
 
-impl std::fmt::Debug for Clock {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<native clock fn>")
+impl TryFrom<&Literal> for NativeValue {
+    type Error = String;
+
+    fn try_from(value: &Literal) -> Result<Self, Self::Error> {
+        match value {
+            Literal::Nil => Ok(NativeValue::Nil),
+            Literal::True => Ok(NativeValue::Bool(true)),
+            Literal::False => Ok(NativeValue::Bool(false)),
+            Literal::Number(n) => Ok(NativeValue::Number(*n)),
+            Literal::String(s) => Ok(NativeValue::String(s.clone())),
+            other => Err(format!("Can't pass {} to a native function", other)),
+        }
     }
 }
 
-#[derive(Clone)]
-pub(crate) struct SysCall;
-
-impl LoxCallable for SysCall {
+impl LoxCallable for NativeFunction {
     fn call(&self, args: Vec<Literal>, _ctx: &mut EvalCtx) -> Result<Literal, ExecError> {
-        let mut args = args.iter();
-        let syscall = args.next().unwrap();
-        let args = args.collect::<Vec<_>>();
-        match syscall {
-            Literal::String(syscall) => {
-                let syscall = syscall.as_str();
-                match syscall {
-                    "exit" => {
-                        let code = args
-                            .get(0)
-                            .map(|arg| match arg {
-                                Literal::Number(num) => Ok(*num as i32),
-                                _ => Err(ExecError {
-                                    message: format!("Expected number as argument to exit syscall"),
-                                    range: super::SourceCodeRange {
-                                        line: 0,
-                                        start_column: 0,
-                                        length: 0,
-                                    },
-                                    backtrace: Backtrace::force_capture(),
-                                }),
-                            })
-                            .unwrap_or(Ok(0))?;
-                        std::process::exit(code);
-                    }
-                    _ => Err(ExecError {
-                        message: format!("Unknown syscall: {}", syscall),
-                        range: super::SourceCodeRange {
-                            line: 0,
-                            start_column: 0,
-                            length: 0,
-                        },
-                        backtrace: Backtrace::force_capture(),
-                    }),
-                }
-            }
-            _ => Err(ExecError {
-                message: format!("Expected string as first argument to syscall"),
-                range: super::SourceCodeRange {
-                    line: 0,
-                    start_column: 0,
-                    length: 0,
-                },
-                backtrace: Backtrace::force_capture(),
-            }),
-        }
+        let args = args
+            .iter()
+            .map(NativeValue::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|message| ExecError::new(message, SourceCodeRange::new(0)))?;
+        NativeFunction::call(self, &args)
+            .map(Literal::from)
+            .map_err(|message| ExecError::new(message, SourceCodeRange::new(0)))
     }
 
     fn arity_matches(&self, arity: usize) -> bool {
-        (1..).contains(&arity)
+        self.arity().matches(arity)
     }
 
     fn print_arity(&self) -> String {
-        "1..".to_string()
+        self.arity().to_string()
     }
-}
-
-impl std::fmt::Display for SysCall {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<native syscall fn>")
-    }
-}
 
-impl std::fmt::Debug for SysCall {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<native syscall fn>")
+    fn name(&self) -> String {
+        self.name().to_string()
     }
 }
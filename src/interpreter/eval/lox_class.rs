@@ -1,23 +1,21 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, rc::Rc};
 
-use super::LoxFunction;
+use super::{EvalCtx, LoxFunction, SourceCodeRange, Stmt, StmtType};
 
-#[derive(Clone, Default)]
-pub(crate) struct LoxClass {
+#[derive(Clone)]
+pub struct LoxClass {
     pub(crate) name: String,
+    pub(crate) superclass: Option<Rc<LoxClass>>,
     pub(crate) methods: HashMap<String, LoxFunction>,
-}
-
-impl PartialEq for LoxClass {
-    fn eq(&self, other: &Self) -> bool {
-        self.name == other.name
-    }
+    /// Where the class is declared
+    pub(crate) range: SourceCodeRange,
 }
 
 impl std::fmt::Debug for LoxClass {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("LoxClass")
             .field("name", &self.name)
+            .field("superclass", &self.superclass)
             .field("methods", &self.methods)
             .finish()
     }
@@ -30,14 +28,41 @@ impl std::fmt::Display for LoxClass {
 }
 
 impl LoxClass {
-    pub(crate) fn new(name: String, _methods: Vec<super::Stmt>) -> Self {
-        let methods = HashMap::new();
-        // for stmt in methods {
-        //     if let super::StmtType::Function(_, name, _, body) = stmt.intern {
-        //         let function = LoxFunction::new(super::FunctionType::Method, name.clone(), body.clone());
-        //         methods.insert(name, function);
-        //     }
-        // }
-        Self { name, methods }
+    pub(crate) fn new(
+        name: String,
+        superclass: Option<Rc<LoxClass>>,
+        methods: &[Stmt],
+        closure: &EvalCtx,
+        range: SourceCodeRange,
+    ) -> Self {
+        let mut method_map = HashMap::new();
+        for stmt in methods {
+            if let StmtType::Function(tipe, name, args, body) = &stmt.intern {
+                let function = LoxFunction {
+                    tipe: tipe.clone(),
+                    name: name.clone(),
+                    args: args.clone(),
+                    body: body.clone(),
+                    closure: closure.clone(),
+                    range: stmt.range,
+                };
+                method_map.insert(name.clone(), function);
+            }
+        }
+        Self {
+            name,
+            superclass,
+            methods: method_map,
+            range,
+        }
+    }
+
+    /// Looks up a method on this class, walking up the superclass chain.
+    pub(crate) fn find_method(&self, name: &str) -> Option<&LoxFunction> {
+        self.methods.get(name).or_else(|| {
+            self.superclass
+                .as_ref()
+                .and_then(|superclass| superclass.find_method(name))
+        })
     }
 }
@@ -1,15 +1,20 @@
-use std::fmt::Display;
+use std::{cell::RefCell, fmt::Display, rc::Rc};
 
-use super::{EvalCtx, ExecError, FunctionType, Literal, LoxCallable, Stmt};
+use super::{
+    ControlFlow, EvalCtx, ExecError, FunctionType, Literal, LoxCallable, LoxInstance,
+    SourceCodeRange, Stmt,
+};
 
 #[derive(Clone)]
-pub(crate) struct LoxFunction {
+pub struct LoxFunction {
     // FunctionType, String, Vec<String>, Box<Stmt>
     pub tipe: FunctionType,
     pub name: String,
     pub args: Vec<String>,
     pub body: Box<Stmt>,
     pub closure: EvalCtx,
+    /// Where the function is declared
+    pub range: SourceCodeRange,
 }
 
 impl std::fmt::Debug for LoxFunction {
@@ -31,22 +36,61 @@ impl LoxCallable for LoxFunction {
             new_ctx.insert(param.clone(), arg.clone());
         }
 
-        self.body.eval(&mut new_ctx)?;
+        let return_value = match self.body.eval(&mut new_ctx)? {
+            ControlFlow::Return(value) => value,
+            _ => Literal::Nil,
+        };
 
-        return Ok(new_ctx.return_value.take().unwrap_or_default());
+        if self.is_initializer() {
+            return Ok(self.this());
+        }
+        return Ok(return_value);
     }
 
     fn arity_matches(&self, arity: usize) -> bool {
-        (0..).contains(&arity)
+        arity == self.args.len()
     }
 
     fn print_arity(&self) -> String {
-        "0".to_string()
+        self.args.len().to_string()
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn range(&self) -> Option<SourceCodeRange> {
+        Some(self.range)
+    }
+}
+
+impl LoxFunction {
+    /// Creates a copy of this method whose closure has `this` bound to `instance`.
+    pub(crate) fn bind(&self, instance: Rc<RefCell<LoxInstance>>) -> LoxFunction {
+        let mut closure = self.closure.new_scope();
+        closure.insert("this".to_string(), Literal::Instance(instance));
+        LoxFunction {
+            closure,
+            ..self.clone()
+        }
+    }
+
+    pub(crate) fn is_initializer(&self) -> bool {
+        matches!(self.tipe, FunctionType::Method) && self.name == "init"
+    }
+
+    fn this(&self) -> Literal {
+        self.closure
+            .variables
+            .borrow()
+            .get("this")
+            .map(|this| this.borrow().clone())
+            .unwrap_or_default()
     }
 }
 
 impl Display for LoxFunction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<fn>")
+        write!(f, "<fn {}>", self.name)
     }
 }
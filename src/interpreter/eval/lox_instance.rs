@@ -1,34 +1,27 @@
-use std::collections::HashMap;
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
-use super::{LoxCallable, LoxClass, LoxFunction};
+use super::{LoxCallable, LoxClass, SourceCodeRange};
 
 use super::ExecResult;
 
-#[derive(Clone, Default)]
-pub(crate) struct LoxInstance {
-    pub(crate) name: String,
-    pub(crate) methods: HashMap<String, LoxFunction>,
+#[derive(Clone)]
+pub struct LoxInstance {
+    pub(crate) class: Rc<LoxClass>,
     pub(crate) fields: HashMap<String, super::Literal>,
 }
 
-impl PartialEq for LoxInstance {
-    fn eq(&self, other: &Self) -> bool {
-        self.name == other.name
-    }
-}
-
 impl std::fmt::Debug for LoxInstance {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("LoxInstance")
-            .field("name", &self.name)
-            .field("methods", &self.methods)
+            .field("class", &self.class.name)
+            .field("fields", &self.fields)
             .finish()
     }
 }
 
 impl std::fmt::Display for LoxInstance {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<instance {}>", self.name)
+        write!(f, "<instance {}>", self.class.name)
     }
 }
 
@@ -48,37 +41,41 @@ impl LoxCallable for LoxInstance {
     fn print_arity(&self) -> String {
         "0".to_string()
     }
+
+    fn name(&self) -> String {
+        self.class.name.clone()
+    }
 }
 
 impl LoxInstance {
-    pub(crate) fn new(class: &LoxClass) -> Self {
-        let methods = HashMap::new();
-        let fields = HashMap::new();
-        // for stmt in methods {
-        //     if let super::StmtType::Function(_, name, _, body) = stmt.intern {
-        //         let function = LoxFunction::new(super::FunctionType::Method, name.clone(), body.clone());
-        //         methods.insert(name, function);
-        //     }
-        // }
+    pub(crate) fn new(class: Rc<LoxClass>) -> Self {
         Self {
-            name: class.name.clone(),
-            methods,
-            fields,
+            class,
+            fields: HashMap::new(),
         }
     }
 
-    pub(crate) fn get(&self, name: &str) -> ExecResult<super::Literal> {
-        self.fields
-            .get(name)
-            .ok_or(super::ExecError::new(
-                format!("Undefined property '{}'.", name),
-                super::SourceCodeRange {
-                    line: 0,
-                    start_column: 0,
-                    length: 0,
-                },
-            ))
-            .cloned()
+    /// Looks up a property on the instance. Fields shadow methods; methods are
+    /// bound to `instance` so that `this` refers to it inside the body.
+    pub(crate) fn get(
+        instance: &Rc<RefCell<LoxInstance>>,
+        name: &str,
+        range: SourceCodeRange,
+    ) -> ExecResult<super::Literal> {
+        if let Some(value) = instance.borrow().fields.get(name) {
+            return Ok(value.clone());
+        }
+
+        let class = instance.borrow().class.clone();
+        if let Some(method) = class.find_method(name) {
+            let bound = method.bind(instance.clone());
+            return Ok(super::Literal::Callable(Box::new(bound)));
+        }
+
+        Err(super::ExecError::new(
+            format!("Undefined property '{}'.", name),
+            range,
+        ))
     }
 
     pub(crate) fn set(
@@ -8,7 +8,11 @@ use lox_function::LoxFunction;
 use lox_instance::LoxInstance;
 use std::{backtrace::Backtrace, cell::RefCell, collections::HashMap, fmt::Display, rc::Rc};
 
-use super::{parser::ast::*, Expr, SourceCodeRange};
+use super::{diagnostic::Diagnostic, parser::ast::*, Expr, SourceCodeRange};
+use crate::{
+    natives::{NativeFunction, Natives},
+    output::Output,
+};
 
 #[derive(Debug)]
 pub struct ExecError {
@@ -16,6 +20,53 @@ pub struct ExecError {
     pub(crate) range: SourceCodeRange,
     #[allow(dead_code)]
     pub(crate) backtrace: Backtrace,
+    /// The Lox functions that were running when the error occurred, innermost first
+    pub(crate) call_stack: Vec<StackFrame>,
+}
+
+/// A call of a Lox function that has not returned yet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackFrame {
+    pub function: String,
+    /// Where the function was called from
+    pub call_site: SourceCodeRange,
+}
+
+impl Display for StackFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "in {}() called at line {}",
+            self.function, self.call_site.line
+        )
+    }
+}
+
+/// The functions currently being called, shared by all scopes of an interpreter.
+#[derive(Debug, PartialEq)]
+pub(crate) struct CallStack {
+    frames: Vec<StackFrame>,
+    max_depth: usize,
+}
+
+/// How many calls may be nested before a "Stack overflow." error is raised
+pub(crate) const DEFAULT_MAX_CALL_DEPTH: usize = 10_000;
+
+/// The call stack is only printed in part when recursion went too deep
+const MAX_REPORTED_FRAMES: usize = 16;
+
+/// How much native stack has to be left before evaluating a statement or an
+/// expression. It has to cover all Rust frames until the next check, which
+/// are big in debug builds.
+const STACK_RED_ZONE: usize = 256 * 1024;
+/// How much native stack is added once less than [`STACK_RED_ZONE`] is left
+const STACK_GROWTH: usize = 4 * 1024 * 1024;
+
+/// Runs `f`, first moving to a new stack segment if the current one is about
+/// to run out. Deep Lox recursion is stopped by the maximum call depth, never
+/// by the host's stack.
+fn with_stack<T>(f: impl FnOnce() -> T) -> T {
+    stacker::maybe_grow(STACK_RED_ZONE, STACK_GROWTH, f)
 }
 
 impl ExecError {
@@ -24,8 +75,14 @@ impl ExecError {
             message,
             range,
             backtrace: Backtrace::capture(),
+            call_stack: Vec::new(),
         }
     }
+
+    /// The Lox functions that were running when the error occurred, innermost first.
+    pub fn call_stack(&self) -> &[StackFrame] {
+        &self.call_stack
+    }
 }
 
 impl Display for ExecError {
@@ -35,6 +92,22 @@ impl Display for ExecError {
     }
 }
 
+impl From<&ExecError> for Diagnostic {
+    fn from(err: &ExecError) -> Self {
+        let mut diagnostic = Diagnostic::error("E0001", err.message.clone(), err.range);
+        for frame in err.call_stack.iter().take(MAX_REPORTED_FRAMES) {
+            diagnostic = diagnostic.with_note(frame.to_string());
+        }
+        if err.call_stack.len() > MAX_REPORTED_FRAMES {
+            diagnostic = diagnostic.with_note(format!(
+                "... and {} more calls",
+                err.call_stack.len() - MAX_REPORTED_FRAMES
+            ));
+        }
+        diagnostic
+    }
+}
+
 type ExecResult<T> = std::result::Result<T, ExecError>;
 
 #[derive(Debug, PartialEq)]
@@ -45,13 +118,10 @@ pub struct EvalCtx {
     variables: Rc<RefCell<HashMap<String, Rc<RefCell<Literal>>>>>,
     globals: Rc<RefCell<HashMap<String, Rc<RefCell<Literal>>>>>,
     enclosing: Option<Rc<RefCell<EvalCtx>>>,
-    /// If the current loop should break
-    break_loop: Rc<RefCell<bool>>,
-    /// If the current loop should continue
-    continue_loop: Rc<RefCell<bool>>,
-    /// If the current function should return
-    return_value: Rc<RefCell<Option<Literal>>>,
     locals: Rc<RefCell<HashMap<ExprId, usize>>>,
+    /// Where `print` writes to, shared by all scopes so it can be redirected at any time
+    output: Rc<RefCell<Output>>,
+    calls: Rc<RefCell<CallStack>>,
 }
 
 impl Clone for EvalCtx {
@@ -60,47 +130,95 @@ impl Clone for EvalCtx {
             variables: self.variables.clone(),
             globals: self.globals.clone(),
             enclosing: self.enclosing.clone(),
-            break_loop: self.break_loop.clone(),
-            continue_loop: self.continue_loop.clone(),
-            return_value: self.return_value.clone(),
             locals: self.locals.clone(),
+            output: self.output.clone(),
+            calls: self.calls.clone(),
         }
     }
 }
 
 impl EvalCtx {
-    pub fn new_globals(locals: HashMap<ExprId, usize>) -> Self {
+    pub fn new_globals(locals: HashMap<ExprId, usize>, natives: &Natives) -> Self {
         let mut globals = HashMap::new();
-        globals.insert(
-            "clock".to_string(),
-            Rc::new(RefCell::new(Literal::Callable(Box::new(
-                crate::interpreter::eval::lox_callable::Clock,
-            )))),
-        );
-        globals.insert(
-            "syscall".to_string(),
-            Rc::new(RefCell::new(Literal::Callable(Box::new(
-                crate::interpreter::eval::lox_callable::SysCall,
-            )))),
-        );
+        for native in natives.iter() {
+            globals.insert(
+                native.name().to_string(),
+                Rc::new(RefCell::new(Literal::Callable(Box::new(native.clone())))),
+            );
+        }
         EvalCtx {
             globals: Rc::new(RefCell::new(globals)),
             variables: Rc::new(RefCell::new(HashMap::new())),
             enclosing: None,
-            break_loop: Rc::new(RefCell::new(false)),
-            continue_loop: Rc::new(RefCell::new(false)),
-            return_value: Rc::new(RefCell::new(None)),
             locals: Rc::new(RefCell::new(locals)),
+            output: Rc::new(RefCell::new(Output::default())),
+            calls: Rc::new(RefCell::new(CallStack {
+                frames: Vec::new(),
+                max_depth: DEFAULT_MAX_CALL_DEPTH,
+            })),
         }
     }
 
+    /// Makes the scope distances of a newly resolved program known to this context.
+    pub(crate) fn add_locals(&self, locals: HashMap<ExprId, usize>) {
+        self.locals.borrow_mut().extend(locals);
+    }
+
+    /// Redirects the output of `print` statements, including those in functions
+    /// that were defined before.
+    pub(crate) fn set_output(&self, output: Output) {
+        *self.output.borrow_mut() = output;
+    }
+
+    /// Limits how deeply calls may be nested.
+    pub(crate) fn set_max_call_depth(&self, max_depth: usize) {
+        self.calls.borrow_mut().max_depth = max_depth;
+    }
+
+    /// Makes a native function available to all code run in this context.
+    pub(crate) fn define_native(&self, native: NativeFunction) {
+        self.globals.borrow_mut().insert(
+            native.name().to_string(),
+            Rc::new(RefCell::new(Literal::Callable(Box::new(native)))),
+        );
+    }
+
+    /// Returns the variables of the outermost scope, sorted by name.
+    pub(crate) fn top_level_variables(&self) -> Vec<(String, Literal)> {
+        let mut variables = self
+            .variables
+            .borrow()
+            .iter()
+            .map(|(name, value)| (name.clone(), value.borrow().clone()))
+            .collect::<Vec<_>>();
+        variables.sort_by(|(a, _), (b, _)| a.cmp(b));
+        variables
+    }
+
+    /// Looks up a top-level variable, falling back to the native functions.
+    pub(crate) fn get_global(&self, name: &str) -> Option<Literal> {
+        if let Some(value) = self.variables.borrow().get(name) {
+            return Some(value.borrow().clone());
+        }
+        self.globals
+            .borrow()
+            .get(name)
+            .map(|value| value.borrow().clone())
+    }
+
     pub fn insert(&mut self, name: String, value: Literal) {
         self.variables
             .borrow_mut()
             .insert(name, Rc::new(RefCell::new(value)));
     }
 
-    pub fn assign(&mut self, name: &str, id: ExprId, value: Literal) -> ExecResult<()> {
+    pub fn assign(
+        &mut self,
+        name: &str,
+        id: ExprId,
+        value: Literal,
+        range: SourceCodeRange,
+    ) -> ExecResult<()> {
         let distance = self.locals.borrow().get(&id).cloned();
         if let Some(distance) = distance {
             let ctx = self.ancestor(distance);
@@ -108,29 +226,19 @@ impl EvalCtx {
                 *scope.borrow_mut() = value;
                 return Ok(());
             }
-            return Err(ExecError {
-                message: format!("Can't assign to undefined local variable '{}'", name),
-                range: SourceCodeRange {
-                    line: 0,
-                    start_column: 0,
-                    length: 0,
-                },
-                backtrace: Backtrace::capture(),
-            });
+            Err(ExecError::new(
+                format!("Can't assign to undefined local variable '{}'", name),
+                range,
+            ))
         } else {
             if let Some(scope) = self.globals.borrow_mut().get_mut(name) {
                 *scope.borrow_mut() = value;
                 return Ok(());
             }
-            return Err(ExecError {
-                message: format!("Can't assign to undefined global variable '{}'", name),
-                range: SourceCodeRange {
-                    line: 0,
-                    start_column: 0,
-                    length: 0,
-                },
-                backtrace: Backtrace::capture(),
-            });
+            Err(ExecError::new(
+                format!("Can't assign to undefined global variable '{}'", name),
+                range,
+            ))
         }
     }
 
@@ -145,6 +253,12 @@ impl EvalCtx {
         }
     }
 
+    fn get_at(&self, distance: usize, name: &str) -> Option<Rc<RefCell<Literal>>> {
+        let ctx = self.ancestor(distance);
+        let x = ctx.borrow().variables.borrow().get(name).cloned();
+        x
+    }
+
     fn ancestor(&self, distance: usize) -> Rc<RefCell<EvalCtx>> {
         let mut ctx = self.clone();
         for _ in 0..distance {
@@ -159,57 +273,50 @@ impl EvalCtx {
             variables: Rc::new(RefCell::new(HashMap::new())),
             globals: self.globals.clone(),
             enclosing: Some(Rc::new(RefCell::new(self.clone()))),
-            break_loop: self.break_loop.clone(),
-            continue_loop: self.continue_loop.clone(),
-            return_value: self.return_value.clone(),
             locals: self.locals.clone(),
+            output: self.output.clone(),
+            calls: self.calls.clone(),
         }
     }
-
-    fn get_break_loop(&self) -> bool {
-        *self.break_loop.borrow()
-    }
-
-    fn get_continue_loop(&self) -> bool {
-        *self.continue_loop.borrow()
-    }
-
-    fn get_return_value(&self) -> Option<Literal> {
-        self.return_value.borrow().clone()
-    }
-
-    fn set_break_loop(&self, value: bool) {
-        *self.break_loop.borrow_mut() = value;
-    }
-
-    fn set_continue_loop(&self, value: bool) {
-        *self.continue_loop.borrow_mut() = value;
-    }
-
-    fn set_return_value(&self, value: Option<Literal>) {
-        *self.return_value.borrow_mut() = value;
-    }
 }
 
 pub trait Eval {
     fn eval(&self, ctx: &mut EvalCtx) -> ExecResult<Literal>;
 }
 
+/// How a statement finished, telling the enclosing loop or function whether
+/// to carry on.
+#[derive(Debug)]
+pub(crate) enum ControlFlow {
+    Normal,
+    Break,
+    Continue,
+    Return(Literal),
+}
+
 impl Stmt {
-    pub fn eval(&self, ctx: &mut EvalCtx) -> ExecResult<()> {
-        // If we're in a loop and we should break or continue, don't execute the statement
-        if ctx.get_break_loop() || ctx.get_continue_loop() || ctx.get_return_value().is_some() {
-            return Ok(());
-        }
+    pub(crate) fn eval(&self, ctx: &mut EvalCtx) -> ExecResult<ControlFlow> {
+        with_stack(|| self.eval_unchecked(ctx))
+    }
+
+    fn eval_unchecked(&self, ctx: &mut EvalCtx) -> ExecResult<ControlFlow> {
         match &self.intern {
             StmtType::Expr(expr) => {
                 expr.eval(ctx)?;
-                Ok(())
+                Ok(ControlFlow::Normal)
             }
             StmtType::Print(expr) => {
                 let value = expr.eval(ctx)?;
-                println!("{}", value);
-                Ok(())
+                let output = ctx.output.borrow().clone();
+                // strings are printed without the quotes they are displayed with
+                let written = match &value {
+                    Literal::String(s) => output.write_line(s),
+                    value => output.write_line(value),
+                };
+                written.map_err(|err| {
+                    ExecError::new(format!("Failed to write output: {}", err), self.range)
+                })?;
+                Ok(ControlFlow::Normal)
             }
             StmtType::Var(name, initalizer) => {
                 let value = match initalizer {
@@ -217,57 +324,64 @@ impl Stmt {
                     None => Literal::Nil,
                 };
                 ctx.insert(name.clone(), value);
-                Ok(())
+                Ok(ControlFlow::Normal)
             }
             StmtType::Block(stmts) => {
-                // ctx.push_scope();
                 let mut new_ctx = ctx.new_scope();
                 for stmt in stmts {
-                    if let Err(e) = stmt.eval(&mut new_ctx) {
-                        // Make sure that the scope is popped before returning the error
-                        return Err(e);
+                    match stmt.eval(&mut new_ctx)? {
+                        ControlFlow::Normal => {}
+                        flow => return Ok(flow),
                     }
-                    //TODO: if we'd want the block-return, we could add that here.
                 }
-                Ok(())
+                Ok(ControlFlow::Normal)
             }
             StmtType::IfStmt(expr, then, els) => {
                 let condition = expr.eval(ctx)?;
                 if bool::from(condition) {
-                    then.eval(ctx)?;
+                    then.eval(ctx)
+                } else if let Some(els) = els {
+                    els.eval(ctx)
                 } else {
-                    if let Some(els) = els {
-                        els.eval(ctx)?;
-                    }
+                    Ok(ControlFlow::Normal)
                 }
-                Ok(())
             }
             StmtType::While(expr, stmt) => {
                 while bool::from(expr.eval(ctx)?) {
-                    stmt.eval(ctx)?;
-                    if ctx.get_break_loop() || ctx.get_return_value().is_some() {
-                        ctx.set_continue_loop(false);
-                        ctx.set_break_loop(false);
-                        break;
+                    match stmt.eval(ctx)? {
+                        ControlFlow::Normal | ControlFlow::Continue => {}
+                        ControlFlow::Break => break,
+                        flow @ ControlFlow::Return(_) => return Ok(flow),
                     }
-                    ctx.set_continue_loop(false);
-                    ctx.set_break_loop(false);
                 }
-                Ok(())
+                Ok(ControlFlow::Normal)
             }
-            StmtType::Break => {
-                ctx.set_break_loop(false);
-                Ok(())
-            }
-            StmtType::Continue => {
-                ctx.set_continue_loop(false);
-                Ok(())
-            }
-            StmtType::Return(expr) => {
-                let literal = expr.eval(ctx)?;
-                ctx.set_return_value(Some(literal));
-                Ok(())
+            StmtType::For(initializer, condition, increment, stmt) => {
+                let mut ctx = ctx.new_scope();
+                if let Some(initializer) = initializer {
+                    initializer.eval(&mut ctx)?;
+                }
+                loop {
+                    if let Some(condition) = condition {
+                        if !bool::from(condition.eval(&mut ctx)?) {
+                            break;
+                        }
+                    }
+                    match stmt.eval(&mut ctx)? {
+                        // the increment also runs after a `continue`
+                        ControlFlow::Normal | ControlFlow::Continue => {}
+                        ControlFlow::Break => break,
+                        flow @ ControlFlow::Return(_) => return Ok(flow),
+                    }
+                    if let Some(increment) = increment {
+                        increment.eval(&mut ctx)?;
+                    }
+                }
+                Ok(ControlFlow::Normal)
             }
+            StmtType::Break => Ok(ControlFlow::Break),
+            StmtType::Continue => Ok(ControlFlow::Continue),
+            StmtType::Return(expr) => Ok(ControlFlow::Return(expr.eval(ctx)?)),
             StmtType::Function(function_type, name, vec, stmt) => {
                 let function = Literal::Callable(Box::new(LoxFunction {
                     tipe: function_type.clone(),
@@ -275,14 +389,41 @@ impl Stmt {
                     args: vec.clone(),
                     body: stmt.clone(),
                     closure: ctx.clone(),
+                    range: self.range,
                 }));
                 ctx.insert(name.clone(), function);
-                Ok(())
+                Ok(ControlFlow::Normal)
             }
-            StmtType::Class(name, methods) => {
-                let class = Literal::Class(LoxClass::new(name.clone(), methods.clone()));
-                ctx.insert(name.clone(), class);
-                Ok(())
+            StmtType::Class(name, superclass, methods) => {
+                let superclass = match superclass {
+                    Some(superclass) => match superclass.eval(ctx)? {
+                        Literal::Class(class) => Some(class),
+                        _ => {
+                            return Err(ExecError::new(
+                                "Superclass must be a class".to_string(),
+                                superclass.range,
+                            ))
+                        }
+                    },
+                    None => None,
+                };
+
+                let class = match superclass {
+                    Some(superclass) => {
+                        let mut closure = ctx.new_scope();
+                        closure.insert("super".to_string(), Literal::Class(superclass.clone()));
+                        LoxClass::new(
+                            name.clone(),
+                            Some(superclass),
+                            methods,
+                            &closure,
+                            self.range,
+                        )
+                    }
+                    None => LoxClass::new(name.clone(), None, methods, ctx, self.range),
+                };
+                ctx.insert(name.clone(), Literal::Class(Rc::new(class)));
+                Ok(ControlFlow::Normal)
             }
         }
     }
@@ -290,23 +431,31 @@ impl Stmt {
 
 impl Eval for Expr {
     fn eval(&self, ctx: &mut EvalCtx) -> ExecResult<Literal> {
+        with_stack(|| self.eval_unchecked(ctx))
+    }
+}
+
+impl Expr {
+    fn eval_unchecked(&self, ctx: &mut EvalCtx) -> ExecResult<Literal> {
         match &*self.intern {
             ExprType::Literal(literal) => Ok(literal.to_owned()),
             ExprType::Grouping(expr) => expr.eval(ctx),
             ExprType::Unary(unary) => unary.eval(ctx),
             ExprType::Binary(binary) => binary.eval(ctx),
             ExprType::Variable(name) => {
-                let res = ctx.get(name, self.id).ok_or(ExecError {
-                    message: format!("Can't get undefined variable {} '{name}'", self.id),
-                    range: self.range.clone(),
-                    backtrace: Backtrace::capture(),
+                // built lazily, capturing a backtrace is expensive in deep recursion
+                let res = ctx.get(name, self.id).ok_or_else(|| {
+                    ExecError::new(
+                        format!("Can't get undefined variable {} '{name}'", self.id),
+                        self.range,
+                    )
                 })?;
                 let value = res.borrow().clone();
                 Ok(value)
             }
             ExprType::Assign(name, expr) => {
                 let value = expr.eval(ctx)?;
-                ctx.assign(name, self.id, value.clone())?;
+                ctx.assign(name, self.id, value.clone(), self.range)?;
                 Ok(value)
             }
             ExprType::Logical(logical) => logical.eval(ctx),
@@ -314,17 +463,12 @@ impl Eval for Expr {
             ExprType::Get(get, name) => {
                 let object = get.eval(ctx)?;
                 match object {
-                    Literal::Instance(instance) => {
-                        if let Some(method) = instance.borrow().methods.get(name) {
-                            Ok(Literal::Callable(Box::new(method.clone())))
-                        } else {
-                            instance.borrow().get(name)
-                        }
-                    }
+                    Literal::Instance(instance) => LoxInstance::get(&instance, name, self.range),
                     _ => Err(ExecError {
                         message: "Only instances have properties".to_string(),
                         range: self.range.clone(),
                         backtrace: Backtrace::capture(),
+                        call_stack: Vec::new(),
                     }),
                 }
             }
@@ -335,7 +479,8 @@ impl Eval for Expr {
                     Literal::Instance(instance) => {
                         // // check, if name exists, before setting it
                         // instance.borrow().get(name)?;
-                        instance.borrow_mut().set(name, value.eval(ctx)?)
+                        let value = value.eval(ctx)?;
+                        instance.borrow_mut().set(name, value)
                     }
                     _ => Err(ExecError::new(
                         "Only instances have fields".to_string(),
@@ -343,6 +488,47 @@ impl Eval for Expr {
                     )),
                 }
             }
+            ExprType::This => {
+                let res = ctx.get("this", self.id).ok_or_else(|| {
+                    ExecError::new(
+                        "Can't use 'this' outside of a class".to_string(),
+                        self.range,
+                    )
+                })?;
+                let value = res.borrow().clone();
+                Ok(value)
+            }
+            ExprType::Super(method) => {
+                let distance = ctx.locals.borrow().get(&self.id).cloned();
+                let superclass = distance.and_then(|distance| ctx.get_at(distance, "super"));
+                // `this` lives in the scope just inside the one that binds `super`
+                let this = distance
+                    .filter(|distance| *distance > 0)
+                    .and_then(|distance| ctx.get_at(distance - 1, "this"));
+                let (Some(superclass), Some(this)) = (superclass, this) else {
+                    return Err(ExecError::new(
+                        "Can't use 'super' outside of a subclass".to_string(),
+                        self.range,
+                    ));
+                };
+
+                let (Literal::Class(superclass), Literal::Instance(instance)) =
+                    (superclass.borrow().clone(), this.borrow().clone())
+                else {
+                    return Err(ExecError::new(
+                        "Invalid 'super' binding".to_string(),
+                        self.range,
+                    ));
+                };
+
+                match superclass.find_method(method) {
+                    Some(method) => Ok(Literal::Callable(Box::new(method.bind(instance)))),
+                    None => Err(ExecError::new(
+                        format!("Undefined property '{}'.", method),
+                        self.range,
+                    )),
+                }
+            }
         }
     }
 }
@@ -356,38 +542,128 @@ impl Eval for Call {
             arguments.push(arg.eval(ctx)?);
         }
 
-        match callee {
-            Literal::Callable(callable) => {
-                if !callable.arity_matches(arguments.len()) {
-                    return Err(ExecError {
-                        message: format!(
-                            "Expected {} arguments but got {}",
-                            callable.print_arity(),
+        call_value(callee, arguments, ctx, self.callee.range)
+    }
+}
+
+/// Calls a function or constructs an instance of a class.
+///
+/// `range` is the source location of the callee and is used for error messages.
+pub(crate) fn call_value(
+    callee: Literal,
+    arguments: Vec<Literal>,
+    ctx: &mut EvalCtx,
+    range: SourceCodeRange,
+) -> ExecResult<Literal> {
+    // checked before the frame is pushed, the callee never runs
+    let function = match &callee {
+        Literal::Callable(callable) => {
+            check_arity(&callable.name(), callable.as_ref(), arguments.len(), range)?;
+            callable.name()
+        }
+        Literal::Class(class) => {
+            match class.find_method("init") {
+                Some(init) => check_arity(&class.name, init, arguments.len(), range)?,
+                None if !arguments.is_empty() => {
+                    return Err(ExecError::new(
+                        format!(
+                            "'{}' expected 0 arguments but got {}",
+                            class.name,
                             arguments.len()
                         ),
-                        range: self.callee.range.clone(),
-                        backtrace: Backtrace::capture(),
-                    });
+                        range,
+                    ));
                 }
-                callable.call(arguments, ctx)
+                None => {}
             }
-            Literal::Class(class) => {
-                let instance = Literal::Instance(Rc::new(RefCell::new(LoxInstance::new(&class))));
-                let init = class.methods.get("init").cloned();
-                if let Some(init) = init {
-                    let mut new_ctx = ctx.new_scope();
-                    new_ctx.insert("this".to_string(), instance.clone());
-                    init.body.eval(&mut new_ctx)?;
+            class.name.clone()
+        }
+        _ => {
+            return Err(ExecError::new(
+                "Can only call functions and classes".to_string(),
+                range,
+            ))
+        }
+    };
+
+    {
+        let mut calls = ctx.calls.borrow_mut();
+        calls.frames.push(StackFrame {
+            function,
+            call_site: range,
+        });
+        if calls.frames.len() > calls.max_depth {
+            let mut err = ExecError::new("Stack overflow.".to_string(), range);
+            err.call_stack = calls.frames.iter().rev().cloned().collect();
+            calls.frames.pop();
+            return Err(err);
+        }
+    }
+
+    let result = with_stack(|| call_unchecked(callee, arguments, ctx, range));
+
+    let mut calls = ctx.calls.borrow_mut();
+    let result = result.map_err(|mut err| {
+        if err.call_stack.is_empty() {
+            err.call_stack = calls.frames.iter().rev().cloned().collect();
+        }
+        err
+    });
+    calls.frames.pop();
+    result
+}
+
+fn call_unchecked(
+    callee: Literal,
+    arguments: Vec<Literal>,
+    ctx: &mut EvalCtx,
+    range: SourceCodeRange,
+) -> ExecResult<Literal> {
+    match callee {
+        Literal::Callable(callable) => {
+            callable.call(arguments, ctx).map_err(|err| {
+                // natives don't know where they were called from
+                if err.range.line == 0 {
+                    ExecError::new(err.message, range)
+                } else {
+                    err
                 }
-                Ok(instance)
+            })
+        }
+        Literal::Class(class) => {
+            let instance = Rc::new(RefCell::new(LoxInstance::new(class.clone())));
+            if let Some(init) = class.find_method("init") {
+                init.bind(instance.clone()).call(arguments, ctx)?;
             }
-            _ => Err(ExecError {
-                message: "Can only call functions and classes".to_string(),
-                range: self.callee.range.clone(),
-                backtrace: Backtrace::capture(),
-            }),
+            Ok(Literal::Instance(instance))
         }
+        _ => Err(ExecError {
+            message: "Can only call functions and classes".to_string(),
+            range,
+            backtrace: Backtrace::capture(),
+            call_stack: Vec::new(),
+        }),
+    }
+}
+
+fn check_arity(
+    name: &str,
+    callable: &dyn LoxCallable,
+    arity: usize,
+    range: SourceCodeRange,
+) -> ExecResult<()> {
+    if callable.arity_matches(arity) {
+        return Ok(());
     }
+    Err(ExecError::new(
+        format!(
+            "'{}' expected {} arguments but got {}",
+            name,
+            callable.print_arity(),
+            arity
+        ),
+        range,
+    ))
 }
 
 impl Eval for Logical {
@@ -409,6 +685,7 @@ impl Eval for Unary {
                 _ => Err(ExecError {
                     message: "Unary minus expects a number".to_string(),
                     backtrace: Backtrace::capture(),
+                    call_stack: Vec::new(),
                     range: self.expr.range.clone(),
                 }),
             },
@@ -425,12 +702,11 @@ impl Eval for Binary {
                 (Literal::String(l), Literal::String(r)) => {
                     Ok(Literal::String(format!("{}{}", l, r)))
                 }
-                (Literal::String(l), other) => Ok(Literal::String(format!("{}{}", l, other))),
-                (other, Literal::String(r)) => Ok(Literal::String(format!("{}{}", other, r))),
                 _ => Err(ExecError {
                     message: "Operands must be two numbers or two strings".to_string(),
                     range: self.left.range.merge(&self.right.range),
                     backtrace: Backtrace::capture(),
+                    call_stack: Vec::new(),
                 }),
             },
             Operator::Minus | Operator::Times | Operator::Div => {
@@ -447,6 +723,7 @@ impl Eval for Binary {
                         message: "Operands must be numbers".to_string(),
                         range: self.left.range.merge(&self.right.range),
                         backtrace: Backtrace::capture(),
+                        call_stack: Vec::new(),
                     }),
                 }
             }
@@ -465,6 +742,7 @@ impl Eval for Binary {
                         message: "Operands must be numbers".to_string(),
                         range: self.left.range.merge(&self.right.range),
                         backtrace: Backtrace::capture(),
+                        call_stack: Vec::new(),
                     }),
                 }
             }
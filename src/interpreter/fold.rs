@@ -0,0 +1,205 @@
+//! Constant folding and dead branch elimination on the AST.
+//!
+//! Runs after resolving and before evaluating or compiling, so both the
+//! tree-walker and the compiler get to skip work whose result is already
+//! known. Only operations both backends agree on are folded: arithmetic and
+//! comparisons of numbers, concatenation of two strings, equality and `!` of
+//! literals, and `and`/`or` with a literal on the left. Anything that would
+//! fail at runtime is left alone so it still fails there. A folded expression
+//! keeps the id and the range of the expression it replaces.
+//!
+//! Branches that can never run are removed, which is why both backends
+//! resolve a program before folding it: e.g. a `return` at the top level
+//! inside `if (false)` is still reported.
+
+use super::parser::ast::{
+    Binary, Expr, ExprType, Literal, Logical, LogicalOperator, Operator, Stmt, StmtType, Unary,
+    UnaryType,
+};
+
+/// Folds every statement of a program.
+pub fn fold(stmts: Vec<Stmt>) -> Vec<Stmt> {
+    stmts.into_iter().map(fold_stmt).collect()
+}
+
+pub fn fold_stmt(stmt: Stmt) -> Stmt {
+    let range = stmt.range;
+    let intern = match stmt.intern {
+        StmtType::Expr(expr) => StmtType::Expr(fold_expr(expr)),
+        StmtType::Print(expr) => StmtType::Print(fold_expr(expr)),
+        StmtType::Return(expr) => StmtType::Return(fold_expr(expr)),
+        StmtType::Var(name, initializer) => StmtType::Var(name, initializer.map(fold_expr)),
+        StmtType::IfStmt(condition, then_branch, else_branch) => {
+            let condition = fold_expr(condition);
+            let then_branch = Box::new(fold_stmt(*then_branch));
+            let else_branch = else_branch.map(|branch| Box::new(fold_stmt(*branch)));
+            match constant(&condition) {
+                Some(literal) if bool::from(literal) => return *then_branch,
+                Some(_) => match else_branch {
+                    Some(branch) => return *branch,
+                    None => StmtType::Block(Vec::new()),
+                },
+                None => StmtType::IfStmt(condition, then_branch, else_branch),
+            }
+        }
+        StmtType::While(condition, body) => {
+            let condition = fold_expr(condition);
+            match constant(&condition) {
+                Some(literal) if !bool::from(literal) => StmtType::Block(Vec::new()),
+                _ => StmtType::While(condition, Box::new(fold_stmt(*body))),
+            }
+        }
+        StmtType::For(initializer, condition, increment, body) => {
+            let initializer = initializer.map(|stmt| Box::new(fold_stmt(*stmt)));
+            let condition = condition.map(fold_expr);
+            match condition.as_ref().and_then(constant) {
+                // only the initializer runs, in the scope of the loop
+                Some(literal) if !bool::from(literal) => {
+                    StmtType::Block(initializer.into_iter().map(|stmt| *stmt).collect())
+                }
+                _ => StmtType::For(
+                    initializer,
+                    condition,
+                    increment.map(fold_expr),
+                    Box::new(fold_stmt(*body)),
+                ),
+            }
+        }
+        StmtType::Block(stmts) => StmtType::Block(fold(stmts)),
+        StmtType::Function(tipe, name, params, body) => {
+            StmtType::Function(tipe, name, params, Box::new(fold_stmt(*body)))
+        }
+        StmtType::Class(name, superclass, methods) => {
+            StmtType::Class(name, superclass.map(fold_expr), fold(methods))
+        }
+        intern @ (StmtType::Break | StmtType::Continue) => intern,
+    };
+    Stmt { intern, range }
+}
+
+pub fn fold_expr(expr: Expr) -> Expr {
+    let Expr { intern, range, id } = expr;
+    let intern = match *intern {
+        ExprType::Grouping(inner) => {
+            let inner = fold_expr(inner);
+            match *inner.intern {
+                ExprType::Literal(literal) => ExprType::Literal(literal),
+                intern => ExprType::Grouping(Expr {
+                    intern: Box::new(intern),
+                    ..inner
+                }),
+            }
+        }
+        ExprType::Unary(Unary { intern, expr }) => {
+            let expr = fold_expr(expr);
+            match fold_unary(&intern, &expr) {
+                Some(literal) => ExprType::Literal(literal),
+                None => ExprType::Unary(Unary { intern, expr }),
+            }
+        }
+        ExprType::Binary(Binary {
+            left,
+            operator,
+            right,
+        }) => {
+            let left = fold_expr(left);
+            let right = fold_expr(right);
+            match fold_binary(&left, &operator, &right) {
+                Some(literal) => ExprType::Literal(literal),
+                None => ExprType::Binary(Binary {
+                    left,
+                    operator,
+                    right,
+                }),
+            }
+        }
+        ExprType::Logical(Logical {
+            left,
+            operator,
+            right,
+        }) => {
+            let left = fold_expr(left);
+            let right = fold_expr(right);
+            match constant(&left).map(bool::from) {
+                // the operand the expression evaluates to, which has a range of its own
+                Some(true) => match operator {
+                    LogicalOperator::Or => return left,
+                    LogicalOperator::And => return right,
+                },
+                Some(false) => match operator {
+                    LogicalOperator::Or => return right,
+                    LogicalOperator::And => return left,
+                },
+                None => ExprType::Logical(Logical {
+                    left,
+                    operator,
+                    right,
+                }),
+            }
+        }
+        ExprType::Assign(name, value) => ExprType::Assign(name, fold_expr(value)),
+        ExprType::Call(mut call) => {
+            call.callee = fold_expr(call.callee);
+            call.arguments = call.arguments.into_iter().map(fold_expr).collect();
+            ExprType::Call(call)
+        }
+        ExprType::Get(object, name) => ExprType::Get(fold_expr(object), name),
+        ExprType::Set(object, name, value) => {
+            ExprType::Set(fold_expr(object), name, fold_expr(value))
+        }
+        intern @ (ExprType::Literal(_)
+        | ExprType::Variable(_)
+        | ExprType::This
+        | ExprType::Super(_)) => intern,
+    };
+    Expr {
+        intern: Box::new(intern),
+        range,
+        id,
+    }
+}
+
+/// The value of `expr` if it is a literal.
+fn constant(expr: &Expr) -> Option<&Literal> {
+    match &*expr.intern {
+        ExprType::Literal(literal) => Some(literal),
+        _ => None,
+    }
+}
+
+fn fold_unary(operator: &UnaryType, expr: &Expr) -> Option<Literal> {
+    match (operator, constant(expr)?) {
+        (UnaryType::Neg, Literal::Number(n)) => Some(Literal::Number(-n)),
+        (UnaryType::Neg, _) => None,
+        (UnaryType::Not, literal) => Some(Literal::from(!bool::from(literal))),
+    }
+}
+
+fn fold_binary(left: &Expr, operator: &Operator, right: &Expr) -> Option<Literal> {
+    let (left, right) = (constant(left)?, constant(right)?);
+    match (operator, left, right) {
+        (Operator::EqualEqual, left, right) => Some(Literal::from(left == right)),
+        (Operator::NEqualEqual, left, right) => Some(Literal::from(left != right)),
+        (Operator::Plus, Literal::String(l), Literal::String(r)) => {
+            Some(Literal::String(format!("{}{}", l, r)))
+        }
+        // the VM compiles `<=` to `!(l > r)`, which differs for NaN
+        (Operator::Leq | Operator::Greq, Literal::Number(l), Literal::Number(r))
+            if l.is_nan() || r.is_nan() =>
+        {
+            None
+        }
+        (operator, Literal::Number(l), Literal::Number(r)) => Some(match operator {
+            Operator::Plus => Literal::Number(l + r),
+            Operator::Minus => Literal::Number(l - r),
+            Operator::Times => Literal::Number(l * r),
+            Operator::Div => Literal::Number(l / r),
+            Operator::Less => Literal::from(l < r),
+            Operator::Leq => Literal::from(l <= r),
+            Operator::Greater => Literal::from(l > r),
+            Operator::Greq => Literal::from(l >= r),
+            Operator::EqualEqual | Operator::NEqualEqual => unreachable!(),
+        }),
+        _ => None,
+    }
+}
@@ -1,12 +1,21 @@
 pub mod token;
 use token::{Token, TokenType};
 
-pub fn tokenize(source: &str) -> Result<Vec<Token>, ()> {
-    let mut had_error = false;
+use super::{diagnostic::Diagnostic, SourceCodeRange};
+
+/// Splits `source` into tokens.
+///
+/// Lexing never stops at the first problem: every error is returned alongside
+/// the tokens, and the offending input is represented by a [`TokenType::Error`]
+/// token so that the parser can keep going and report its own errors as well.
+pub fn tokenize(source: &str) -> (Vec<Token>, Vec<Diagnostic>) {
+    let mut errors = Vec::new();
     let mut tokens = Vec::new();
     let chars = source.chars().collect::<Vec<char>>();
 
     let mut line = 0;
+    // index of the first character of the current line, used to compute columns
+    let mut line_start = 0;
     let mut i = 0;
     while i < chars.len() {
         let char = chars[i];
@@ -17,7 +26,7 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, ()> {
                     char.try_into().unwrap(),
                     char.to_string(),
                     line + 1,
-                    i + 1,
+                    i - line_start + 1,
                     1,
                 ));
                 i += 1;
@@ -32,23 +41,25 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, ()> {
                         ('<', '=') => (2, TokenType::LessEqual),
                         ('>', '=') => (2, TokenType::GreaterEqual),
                         ('=', _) | ('!', _) | ('<', _) | ('>', _) => (1, char.try_into().unwrap()),
-                        _ => {
-                            eprintln!("[line {}] Error: Unexpected character", line + 1);
-                            had_error = true;
-                            (0, TokenType::EOF)
-                        }
+                        _ => unreachable!(),
                     };
                     tokens.push(Token::new(
                         token_type,
                         chars[i..i + len].iter().collect(),
                         line + 1,
-                        i + 1,
+                        i - line_start + 1,
                         len,
                     ));
                     i += len;
                 } else {
                     let token_type = char.try_into().unwrap();
-                    tokens.push(Token::new(token_type, char.to_string(), line + 1, i + 1, 1));
+                    tokens.push(Token::new(
+                        token_type,
+                        char.to_string(),
+                        line + 1,
+                        i - line_start + 1,
+                        1,
+                    ));
                     i += 1;
                 }
             }
@@ -64,6 +75,11 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, ()> {
                         continue;
                     }
                     if next_char == '*' {
+                        let start = SourceCodeRange {
+                            line: line + 1,
+                            start_column: i - line_start + 1,
+                            length: 2,
+                        };
                         let mut nestings = 1;
                         i += 2;
                         while i < chars.len() && nestings > 0 {
@@ -77,11 +93,19 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, ()> {
                                 }
                                 ('\n', _) => {
                                     line += 1;
+                                    line_start = i + 1;
                                 }
                                 _ => (),
                             }
                             i += 1;
                         }
+                        if nestings > 0 {
+                            errors.push(Diagnostic::error(
+                                "L0003",
+                                "Unterminated block comment.".to_string(),
+                                start,
+                            ));
+                        }
                         // The closing "/"
                         i += 1;
                         continue;
@@ -91,7 +115,7 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, ()> {
                     TokenType::Slash,
                     char.to_string(),
                     line + 1,
-                    i + 1,
+                    i - line_start + 1,
                     1,
                 ));
                 i += 1;
@@ -104,26 +128,48 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, ()> {
             '\n' => {
                 line += 1;
                 i += 1;
+                line_start = i;
             }
             // handle strings
             '"' => {
+                let start_line = line;
+                let start_column = i - line_start + 1;
                 let mut j = i + 1;
                 while j < chars.len() && chars[j] != '"' {
                     if chars[j] == '\n' {
                         line += 1;
+                        line_start = j + 1;
                     }
                     j += 1;
                 }
                 if j == chars.len() {
-                    eprintln!("[line {}] Error: Unterminated string.", line + 1);
-                    had_error = true;
-                    break;
+                    errors.push(
+                        Diagnostic::error(
+                            "L0002",
+                            "Unterminated string.".to_string(),
+                            SourceCodeRange {
+                                line: start_line + 1,
+                                start_column,
+                                length: 1,
+                            },
+                        )
+                        .with_note("add a closing '\"' to end the string".to_string()),
+                    );
+                    tokens.push(Token::new(
+                        TokenType::Error("Unterminated string.".to_string()),
+                        chars[i..j].iter().collect(),
+                        start_line + 1,
+                        start_column,
+                        j - i,
+                    ));
+                    i = j;
+                    continue;
                 }
                 tokens.push(Token::new(
                     TokenType::String(chars[i + 1..j].iter().collect()),
                     chars[i..j + 1].iter().collect(),
-                    line + 1,
-                    i + 1,
+                    start_line + 1,
+                    start_column,
                     j - i + 1,
                 ));
                 i = j + 1;
@@ -149,7 +195,7 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, ()> {
                     TokenType::Number(parsed_number),
                     lexeme.to_string(),
                     line + 1,
-                    i + 1,
+                    i - line_start + 1,
                     j - i,
                 ));
                 i = j;
@@ -166,22 +212,45 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, ()> {
                         .as_str()
                         .try_into()
                         .unwrap_or(TokenType::Identifier(lexeme.clone()));
-                    tokens.push(Token::new(token_type, lexeme, line + 1, i + 1, j - i));
+                    tokens.push(Token::new(
+                        token_type,
+                        lexeme,
+                        line + 1,
+                        i - line_start + 1,
+                        j - i,
+                    ));
                     i = j;
                 } else {
-                    eprintln!("[line {}] Error: Unexpected character: {}", line + 1, char);
-                    had_error = true;
+                    let message = format!("Unexpected character: {}", char);
+                    errors.push(Diagnostic::error(
+                        "L0001",
+                        message.clone(),
+                        SourceCodeRange {
+                            line: line + 1,
+                            start_column: i - line_start + 1,
+                            length: 1,
+                        },
+                    ));
+                    tokens.push(Token::new(
+                        TokenType::Error(message),
+                        char.to_string(),
+                        line + 1,
+                        i - line_start + 1,
+                        1,
+                    ));
                     i += 1;
                 }
             }
         }
     }
 
-    tokens.push(Token::new(TokenType::EOF, String::new(), 0, 0, 0));
+    tokens.push(Token::new(
+        TokenType::EOF,
+        String::new(),
+        line + 1,
+        chars.len().saturating_sub(line_start) + 1,
+        0,
+    ));
 
-    if had_error {
-        Err(())
-    } else {
-        Ok(tokens)
-    }
+    (tokens, errors)
 }
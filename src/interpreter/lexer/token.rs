@@ -6,9 +6,9 @@ use crate::interpreter::SourceCodeRange;
 
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
-pub(crate) struct Token {
-    pub(crate) inner: TokenType,
-    pub(crate) lexeme: String,
+pub struct Token {
+    pub inner: TokenType,
+    pub lexeme: String,
     // pub(crate) line: usize,
     // pub(crate) start_column: usize,
     // pub(crate) length: usize,
@@ -102,6 +102,10 @@ pub enum TokenType {
     Var,
     While,
 
+    /// Input the lexer could not make sense of. The lexer has already reported
+    /// it, so the parser drops these tokens before it starts.
+    #[strum(disabled)]
+    Error(String),
     EOF,
 }
 
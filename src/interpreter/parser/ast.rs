@@ -9,13 +9,13 @@ use crate::interpreter::{
 };
 
 #[derive(Debug, Clone)]
-pub(crate) struct Stmt {
+pub struct Stmt {
     pub intern: StmtType,
     pub range: SourceCodeRange,
 }
 
 impl Stmt {
-    pub(crate) fn into_format(&self) -> super::format::StmtFormatter {
+    pub fn into_format(&self) -> super::format::StmtFormatter {
         super::format::StmtFormatter {
             stmt: self,
             print_block: true,
@@ -28,6 +28,17 @@ impl Stmt {
             print_block: false,
         }
     }
+
+    /// Whether `self` is a `return` with a value. A bare `return;` is parsed
+    /// as returning a `nil` that has the range of the keyword.
+    pub(crate) fn returns_value(&self) -> bool {
+        match &self.intern {
+            StmtType::Return(expr) => {
+                !matches!(*expr.intern, ExprType::Literal(Literal::Nil)) || expr.range != self.range
+            }
+            _ => false,
+        }
+    }
 }
 
 impl Display for Stmt {
@@ -37,22 +48,24 @@ impl Display for Stmt {
 }
 
 #[derive(Debug, Clone)]
-pub(crate) enum StmtType {
+pub enum StmtType {
     Expr(Expr),
     IfStmt(Expr, Box<Stmt>, Option<Box<Stmt>>),
     Print(Expr),
     Return(Expr),
     Var(String, Option<Expr>),
     While(Expr, Box<Stmt>),
+    /// Initializer, condition, increment and body
+    For(Option<Box<Stmt>>, Option<Expr>, Option<Expr>, Box<Stmt>),
     Block(Vec<Stmt>),
     Break,
     Continue,
     Function(FunctionType, String, Vec<String>, Box<Stmt>),
-    Class(String, Vec<Stmt>),
+    Class(String, Option<Expr>, Vec<Stmt>),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub(crate) struct ExprId(pub usize);
+pub struct ExprId(pub usize);
 
 impl Display for ExprId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -61,7 +74,7 @@ impl Display for ExprId {
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct Expr {
+pub struct Expr {
     pub intern: Box<ExprType>,
     pub range: SourceCodeRange,
     pub id: ExprId,
@@ -74,7 +87,7 @@ impl Display for Expr {
 }
 
 #[derive(Debug, Clone)]
-pub(crate) enum ExprType {
+pub enum ExprType {
     Literal(Literal),
     Grouping(Expr),
     Unary(Unary),
@@ -85,6 +98,8 @@ pub(crate) enum ExprType {
     Call(Call),
     Get(Expr, String),
     Set(Expr, String, Expr),
+    This,
+    Super(String),
 }
 
 impl Display for ExprType {
@@ -100,12 +115,14 @@ impl Display for ExprType {
             ExprType::Call(call) => write!(f, "{call}"),
             ExprType::Get(expr, name) => write!(f, "{expr}.{name}"),
             ExprType::Set(expr, name, value) => write!(f, "{expr}.{name} = {value}"),
+            ExprType::This => write!(f, "this"),
+            ExprType::Super(method) => write!(f, "super.{method}"),
         }
     }
 }
 
 #[derive(Debug, Clone)]
-pub(crate) enum FunctionType {
+pub enum FunctionType {
     Function,
     Method,
 }
@@ -120,7 +137,7 @@ impl FunctionType {
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct Call {
+pub struct Call {
     pub callee: Expr,
     pub arguments: Vec<Expr>,
 }
@@ -142,8 +159,8 @@ impl Display for Call {
     }
 }
 
-#[derive(Clone, PartialEq, Default)]
-pub(crate) enum Literal {
+#[derive(Clone, Default)]
+pub enum Literal {
     Number(f64),
     String(String),
     True,
@@ -151,10 +168,27 @@ pub(crate) enum Literal {
     #[default]
     Nil,
     Callable(Box<dyn LoxCallable>),
-    Class(LoxClass),
+    Class(Rc<LoxClass>),
     Instance(Rc<RefCell<LoxInstance>>),
 }
 
+impl PartialEq for Literal {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Literal::Number(a), Literal::Number(b)) => a == b,
+            (Literal::String(a), Literal::String(b)) => a == b,
+            (Literal::True, Literal::True)
+            | (Literal::False, Literal::False)
+            | (Literal::Nil, Literal::Nil) => true,
+            (Literal::Callable(a), Literal::Callable(b)) => a == b,
+            // classes and instances are only equal to themselves, like on the VM
+            (Literal::Class(a), Literal::Class(b)) => Rc::ptr_eq(a, b),
+            (Literal::Instance(a), Literal::Instance(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
 impl From<bool> for Literal {
     fn from(b: bool) -> Self {
         match b {
@@ -221,7 +255,7 @@ impl Display for Literal {
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct Unary {
+pub struct Unary {
     pub intern: UnaryType,
     pub expr: Expr,
 }
@@ -233,7 +267,7 @@ impl Display for Unary {
 }
 
 #[derive(Debug, Clone, Display)]
-pub(crate) enum UnaryType {
+pub enum UnaryType {
     #[strum(serialize = "!")]
     Not,
     #[strum(serialize = "-")]
@@ -251,7 +285,7 @@ impl From<&TokenType> for UnaryType {
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct Binary {
+pub struct Binary {
     pub left: Expr,
     pub operator: Operator,
     pub right: Expr,
@@ -264,7 +298,7 @@ impl Display for Binary {
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct Logical {
+pub struct Logical {
     pub left: Expr,
     pub operator: LogicalOperator,
     pub right: Expr,
@@ -277,7 +311,7 @@ impl Display for Logical {
 }
 
 #[derive(Debug, Clone)]
-pub(crate) enum LogicalOperator {
+pub enum LogicalOperator {
     And,
     Or,
 }
@@ -302,7 +336,7 @@ impl Display for LogicalOperator {
 }
 
 #[derive(Debug, Clone, Display)]
-pub(crate) enum Operator {
+pub enum Operator {
     #[strum(serialize = "==")]
     EqualEqual,
     #[strum(serialize = "!=")]
@@ -59,6 +59,26 @@ impl Display for StmtFormatter<'_> {
                 }
                 write!(f, "}}")
             }
+            StmtType::For(initializer, condition, increment, stmt) => {
+                match initializer {
+                    Some(initializer) => write!(f, "for ({}", initializer.into_format())?,
+                    None => write!(f, "for (;")?,
+                }
+                match condition {
+                    Some(condition) => write!(f, " {};", condition)?,
+                    None => write!(f, ";")?,
+                }
+                match increment {
+                    Some(increment) => writeln!(f, " {}) {{", increment)?,
+                    None => writeln!(f, ") {{")?,
+                }
+                let mut result = String::new();
+                result.push_str(&format!("{}", stmt.into_format_no_block()));
+                for line in result.lines() {
+                    writeln!(f, "  {}", line)?;
+                }
+                write!(f, "}}")
+            }
             StmtType::Break => write!(f, "break;"),
             StmtType::Continue => write!(f, "continue;"),
             StmtType::Function(function_type, name, args, inner) => {
@@ -80,8 +100,11 @@ impl Display for StmtFormatter<'_> {
                 }
                 write!(f, "}}\n")
             }
-            StmtType::Class(name, methods) => {
-                write!(f, "class {} {{\n", name)?;
+            StmtType::Class(name, superclass, methods) => {
+                match superclass {
+                    Some(superclass) => write!(f, "class {} < {} {{\n", name, superclass)?,
+                    None => write!(f, "class {} {{\n", name)?,
+                }
                 let result = methods
                     .iter()
                     .map(|method| format!("{}", method.into_format()))
@@ -1,5 +1,6 @@
 use std::{backtrace::Backtrace, fmt::Display};
 
+use super::diagnostic::Diagnostic;
 use super::token::{Token, TokenType};
 
 pub(crate) mod ast;
@@ -21,6 +22,16 @@ impl Display for ParserError {
     }
 }
 
+impl From<&ParserError> for Diagnostic {
+    fn from(err: &ParserError) -> Self {
+        let diagnostic = Diagnostic::error("P0001", err.message.clone(), err.token.range);
+        match err.token.inner {
+            TokenType::EOF => diagnostic.with_note("reached the end of the input".to_string()),
+            _ => diagnostic,
+        }
+    }
+}
+
 type Result<T> = std::result::Result<T, ParserError>;
 
 struct ExprIdCounter {
@@ -42,6 +53,7 @@ pub struct ParserInstance {
     pub current: usize,
     pub had_error: bool,
     pub tokens: Vec<Token>,
+    diagnostics: Vec<Diagnostic>,
     exp_id_counter: ExprIdCounter,
 }
 
@@ -55,10 +67,11 @@ impl ParserInstance {
     }
 
     fn error(&mut self, token: &Token, message: &str) {
-        match token.inner {
-            TokenType::EOF => self.report(token.range.line, " at end", message),
-            _ => self.report(token.range.line, "", message),
-        }
+        self.report(&ParserError {
+            message: message.to_string(),
+            token: token.clone(),
+            backtrace: Backtrace::capture(),
+        });
     }
 
     fn synchronize(&mut self) {
@@ -85,46 +98,62 @@ impl ParserInstance {
         }
     }
 
-    fn report(&mut self, line: usize, where_: &str, message: &str) {
-        eprintln!("[line {}] Error{}: {}", line, where_, message);
+    fn report(&mut self, err: &ParserError) {
+        self.diagnostics.push(err.into());
         self.had_error = true;
     }
 
-    pub fn new(tokens: Vec<Token>) -> Self {
+    pub fn new(mut tokens: Vec<Token>) -> Self {
+        // the lexer has reported these already, parsing goes on without them
+        tokens.retain(|token| !matches!(token.inner, TokenType::Error(_)));
         Self {
             current: 0,
             tokens,
             had_error: false,
+            diagnostics: Vec::new(),
             exp_id_counter: ExprIdCounter::new(),
         }
     }
 
-    pub fn parse_expr(&mut self) -> Result<Expr> {
+    /// Starts numbering expressions at `first_id` instead of 1, so that ids stay
+    /// unique when several programs share one evaluation context.
+    pub fn with_first_expr_id(mut self, first_id: usize) -> Self {
+        self.exp_id_counter.counter = first_id.saturating_sub(1);
+        self
+    }
+
+    /// The id the next parsed expression would get.
+    pub fn next_expr_id(&self) -> usize {
+        self.exp_id_counter.counter + 1
+    }
+
+    pub fn parse_expr(&mut self) -> std::result::Result<Expr, Vec<Diagnostic>> {
         match self.expression() {
-            Ok(expr) => Ok(expr),
+            Ok(expr) if !self.had_error => Ok(expr),
+            Ok(_) => Err(std::mem::take(&mut self.diagnostics)),
             Err(err) => {
-                self.error(&err.token, &err.message);
+                self.report(&err);
                 self.synchronize();
-                return Err(err);
+                return Err(std::mem::take(&mut self.diagnostics));
             }
         }
     }
 
-    pub fn parse(&mut self) -> std::result::Result<Vec<Stmt>, ()> {
+    pub fn parse(&mut self) -> std::result::Result<Vec<Stmt>, Vec<Diagnostic>> {
         let mut statements = Vec::new();
         while !self.is_at_end() {
             let declaration = self.declaration();
             match declaration {
                 Ok(declaration) => statements.push(declaration),
                 Err(err) => {
-                    self.error(&err.token, &err.message);
+                    self.report(&err);
                     self.synchronize();
                 }
             }
         }
 
         if self.had_error {
-            return Err(());
+            return Err(std::mem::take(&mut self.diagnostics));
         } else {
             return Ok(statements);
         }
@@ -135,11 +164,9 @@ impl ParserInstance {
             return Ok(self.advance());
         };
 
-        let token = self.peek().to_owned();
-        self.error(&token, message);
         Err(ParserError {
-            message: "Parse error".to_string(),
-            token,
+            message: message.to_string(),
+            token: self.peek().to_owned(),
             backtrace: Backtrace::force_capture(),
         })
     }
@@ -176,6 +203,28 @@ impl ParserInstance {
             unreachable!()
         };
 
+        let superclass = if self.mtch(vec![TokenType::Less]) {
+            match self.peek().inner.clone() {
+                TokenType::Identifier(superclass) => {
+                    self.advance();
+                    Some(Expr {
+                        range: self.previous().range,
+                        intern: Box::new(ExprType::Variable(superclass)),
+                        id: self.exp_id_counter.next(),
+                    })
+                }
+                _ => {
+                    return Err(ParserError {
+                        message: "Expect superclass name.".to_string(),
+                        token: self.peek().to_owned(),
+                        backtrace: Backtrace::force_capture(),
+                    })
+                }
+            }
+        } else {
+            None
+        };
+
         self.consume(
             TokenType::LeftBrace,
             "Expect '{' before class body.".to_string().as_str(),
@@ -193,7 +242,7 @@ impl ParserInstance {
 
         return Ok(Stmt {
             range: range.merge(&self.previous().range),
-            intern: StmtType::Class(name, methods),
+            intern: StmtType::Class(name, superclass, methods),
         });
     }
 
@@ -369,13 +418,9 @@ impl ParserInstance {
         };
 
         let condition = if !self.check(TokenType::Semicolon) {
-            self.expression()?
+            Some(self.expression()?)
         } else {
-            Expr {
-                range: self.previous().range.clone(),
-                intern: Box::new(ExprType::Literal(Literal::True)),
-                id: self.exp_id_counter.next(),
-            }
+            None
         };
         self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
 
@@ -386,34 +431,17 @@ impl ParserInstance {
         };
         self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
 
-        let mut body = self.statement()?;
-
-        if let Some(increment) = increment {
-            body = Stmt {
-                range: body.range.merge(&increment.range),
-                intern: StmtType::Block(vec![
-                    body,
-                    Stmt {
-                        range: increment.range.clone(),
-                        intern: StmtType::Expr(increment),
-                    },
-                ]),
-            };
-        }
-
-        body = Stmt {
-            range: condition.range.merge(&body.range),
-            intern: StmtType::While(condition, Box::new(body)),
-        };
-
-        if let Some(initializer) = initializer {
-            body = Stmt {
-                range: initializer.range.merge(&body.range),
-                intern: StmtType::Block(vec![initializer, body]),
-            };
-        }
+        let body = self.statement()?;
 
-        return Ok(body);
+        let range = initializer
+            .as_ref()
+            .map(|initializer| initializer.range)
+            .or(condition.as_ref().map(|condition| condition.range))
+            .unwrap_or(body.range);
+        Ok(Stmt {
+            range: range.merge(&body.range),
+            intern: StmtType::For(initializer.map(Box::new), condition, increment, Box::new(body)),
+        })
     }
 
     fn if_statement(&mut self) -> Result<Stmt> {
@@ -597,8 +625,7 @@ impl ParserInstance {
                     TokenType::Less,
                     TokenType::LessEqual,
                 ]) {
-                    let right = self.term()?;
-                    println!("Discarding Right: {}", right);
+                    self.term()?;
                 }
 
                 // self.print_remaining();
@@ -704,8 +731,7 @@ impl ParserInstance {
         loop {
             if self.mtch(vec![TokenType::LeftParen]) {
                 expr = self.finish_call(expr)?;
-            }
-            if self.mtch(vec![TokenType::Dot]) {
+            } else if self.mtch(vec![TokenType::Dot]) {
                 let name = match self.peek().inner {
                     TokenType::Identifier(_) => self.advance(),
                     _ => {
@@ -760,8 +786,8 @@ impl ParserInstance {
         });
     }
 
-    // primary        → NUMBER | STRING | "true" | "false" | "nil"
-    //            | "(" expression ")" ;
+    // primary        → NUMBER | STRING | "true" | "false" | "nil" | "this"
+    //            | "(" expression ")" | "super" "." IDENTIFIER ;
     fn primary(&mut self) -> Result<Expr> {
         if self.mtch(vec![TokenType::False]) {
             return Ok(Expr {
@@ -784,6 +810,33 @@ impl ParserInstance {
                 id: self.exp_id_counter.next(),
             });
         }
+        if self.mtch(vec![TokenType::Super]) {
+            let keyword = self.previous().range;
+            self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
+            let method = match self.peek().inner.clone() {
+                TokenType::Identifier(method) => method,
+                _ => {
+                    return Err(ParserError {
+                        message: "Expect superclass method name.".to_string(),
+                        token: self.peek().to_owned(),
+                        backtrace: Backtrace::force_capture(),
+                    })
+                }
+            };
+            self.advance();
+            return Ok(Expr {
+                range: keyword.merge(&self.previous().range),
+                intern: Box::new(ExprType::Super(method)),
+                id: self.exp_id_counter.next(),
+            });
+        }
+        if self.mtch(vec![TokenType::This]) {
+            return Ok(Expr {
+                range: self.previous().range.clone(),
+                intern: Box::new(ExprType::This),
+                id: self.exp_id_counter.next(),
+            });
+        }
 
         match self.peek().inner.clone() {
             TokenType::Number(n) => {
@@ -1,27 +1,106 @@
 use std::collections::HashMap;
 
-use super::{parser::ast::*, Expr, SourceCodeRange, Stmt};
+use super::{diagnostic::Diagnostic, parser::ast::*, Expr, SourceCodeRange, Stmt};
 
 #[derive(Debug, Clone, Copy)]
 enum FunctionType {
     None,
     Function,
+    Method,
+    Initializer,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
 }
 
 #[derive(Debug)]
 #[allow(dead_code)]
-pub(crate) enum ResolverError {
-    DoubleDeclare(String, SourceCodeRange),
+pub enum ResolverError {
+    /// Name, range of the new declaration and range of the previous one
+    DoubleDeclare(String, SourceCodeRange, SourceCodeRange),
     ReturnOutsideFunction(SourceCodeRange),
+    ReturnFromInitializer(SourceCodeRange),
     BreakOutsideLoop(SourceCodeRange),
     ContinueOutsideLoop(SourceCodeRange),
+    ThisOutsideClass(SourceCodeRange),
+    InheritFromSelf(String, SourceCodeRange),
+    SuperOutsideClass(SourceCodeRange),
+    SuperWithoutSuperclass(SourceCodeRange),
+    ReadInOwnInitializer(String, SourceCodeRange),
+}
+
+impl From<&ResolverError> for Diagnostic {
+    fn from(err: &ResolverError) -> Self {
+        match err {
+            ResolverError::DoubleDeclare(name, range, previous) => Diagnostic::error(
+                "R0001",
+                format!("Already a variable named '{}' in this scope.", name),
+                *range,
+            )
+            .with_label(*previous, format!("'{}' first declared here", name)),
+            ResolverError::ReturnOutsideFunction(range) => Diagnostic::error(
+                "R0002",
+                "Can't return from top-level code.".to_string(),
+                *range,
+            ),
+            ResolverError::ReturnFromInitializer(range) => Diagnostic::error(
+                "R0010",
+                "Can't return a value from an initializer.".to_string(),
+                *range,
+            ),
+            ResolverError::BreakOutsideLoop(range) => Diagnostic::error(
+                "R0003",
+                "Can't use 'break' outside of a loop.".to_string(),
+                *range,
+            ),
+            ResolverError::ContinueOutsideLoop(range) => Diagnostic::error(
+                "R0004",
+                "Can't use 'continue' outside of a loop.".to_string(),
+                *range,
+            ),
+            ResolverError::ThisOutsideClass(range) => Diagnostic::error(
+                "R0005",
+                "Can't use 'this' outside of a class.".to_string(),
+                *range,
+            ),
+            ResolverError::InheritFromSelf(name, range) => Diagnostic::error(
+                "R0006",
+                "A class can't inherit from itself.".to_string(),
+                *range,
+            )
+            .with_note(format!("'{}' is the class being declared", name)),
+            ResolverError::SuperOutsideClass(range) => Diagnostic::error(
+                "R0007",
+                "Can't use 'super' outside of a class.".to_string(),
+                *range,
+            ),
+            ResolverError::SuperWithoutSuperclass(range) => Diagnostic::error(
+                "R0008",
+                "Can't use 'super' in a class with no superclass.".to_string(),
+                *range,
+            )
+            .with_note("declare a superclass with 'class Name < Superclass'".to_string()),
+            ResolverError::ReadInOwnInitializer(name, range) => Diagnostic::error(
+                "R0009",
+                "Can't read local variable in its own initializer.".to_string(),
+                *range,
+            )
+            .with_note(format!("'{}' is not defined until its initializer finishes", name)),
+        }
+    }
 }
 
 pub(crate) type ResolverResult<T> = Result<T, ResolverError>;
 
 pub(crate) struct Resolver {
-    scopes: Vec<HashMap<String, bool>>,
+    /// Maps each name to whether it has been defined yet and where it was declared
+    scopes: Vec<HashMap<String, (bool, SourceCodeRange)>>,
     current_function: FunctionType,
+    current_class: ClassType,
     is_in_loop: bool,
     resolved_exprs: HashMap<ExprId, usize>,
 }
@@ -41,6 +120,7 @@ impl Resolver {
         Self {
             scopes: vec![HashMap::new()],
             current_function: FunctionType::None,
+            current_class: ClassType::None,
             is_in_loop: false,
             resolved_exprs: HashMap::new(),
         }
@@ -50,6 +130,27 @@ impl Resolver {
         self.resolved_exprs
     }
 
+    /// Hands out the expressions resolved so far, keeping the scopes intact so
+    /// that later programs can refer to the globals declared by earlier ones.
+    pub(crate) fn take_resolved_exprs(&mut self) -> HashMap<ExprId, usize> {
+        std::mem::take(&mut self.resolved_exprs)
+    }
+
+    /// Declares a global that was defined from outside of Lox code.
+    pub(crate) fn define_global(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.first_mut() {
+            scope.insert(name.to_string(), (true, SourceCodeRange::new(0)));
+        }
+    }
+
+    /// Returns to the global scope after resolving a program failed halfway.
+    pub(crate) fn recover(&mut self) {
+        self.scopes.truncate(1);
+        self.current_function = FunctionType::None;
+        self.current_class = ClassType::None;
+        self.is_in_loop = false;
+    }
+
     pub(crate) fn resolve(&mut self, stmts: &[Stmt]) -> ResolverResult<()> {
         for stmt in stmts {
             self.resolve_stmt(stmt)?;
@@ -74,11 +175,50 @@ impl Resolver {
             StmtType::Function(_, ref name, ref args, ref body) => {
                 self.declare(name, &stmt.range)?;
                 self.define(name);
-                self.resolve_function(args, body)?;
+                self.resolve_function(args, body, FunctionType::Function)?;
             }
-            StmtType::Class(ref name, ref _methods) => {
+            StmtType::Class(ref name, ref superclass, ref methods) => {
+                let enclosing_class = self.current_class;
+                self.current_class = ClassType::Class;
+
                 self.declare(name, &stmt.range)?;
                 self.define(name);
+
+                if let Some(superclass) = superclass {
+                    if let ExprType::Variable(ref superclass_name) = *superclass.intern {
+                        if superclass_name == name {
+                            return Err(ResolverError::InheritFromSelf(
+                                name.clone(),
+                                superclass.range,
+                            ));
+                        }
+                    }
+                    self.current_class = ClassType::Subclass;
+                    self.resolve_expr(superclass)?;
+
+                    self.begin_scope();
+                    self.define("super");
+                }
+
+                self.begin_scope();
+                self.define("this");
+                for method in methods {
+                    if let StmtType::Function(_, ref name, ref args, ref body) = method.intern {
+                        let function_type = if name == "init" {
+                            FunctionType::Initializer
+                        } else {
+                            FunctionType::Method
+                        };
+                        self.resolve_function(args, body, function_type)?;
+                    }
+                }
+                self.end_scope();
+
+                if superclass.is_some() {
+                    self.end_scope();
+                }
+
+                self.current_class = enclosing_class;
             }
             StmtType::Expr(ref expr) => {
                 self.resolve_expr(expr)?;
@@ -94,8 +234,14 @@ impl Resolver {
                 self.resolve_expr(expr)?;
             }
             StmtType::Return(ref expr) => {
-                if let FunctionType::None = self.current_function {
-                    return Err(ResolverError::ReturnOutsideFunction(stmt.range.clone()));
+                match self.current_function {
+                    FunctionType::None => {
+                        return Err(ResolverError::ReturnOutsideFunction(stmt.range.clone()));
+                    }
+                    FunctionType::Initializer if stmt.returns_value() => {
+                        return Err(ResolverError::ReturnFromInitializer(stmt.range));
+                    }
+                    _ => {}
                 }
                 self.resolve_expr(expr)?;
             }
@@ -106,6 +252,24 @@ impl Resolver {
                 self.resolve_stmt(body)?;
                 self.is_in_loop = enclosing_loop;
             }
+            StmtType::For(ref initializer, ref cond, ref increment, ref body) => {
+                // the initializer's variable is only visible inside the loop
+                self.begin_scope();
+                if let Some(initializer) = initializer {
+                    self.resolve_stmt(initializer)?;
+                }
+                let enclosing_loop = self.is_in_loop;
+                self.is_in_loop = true;
+                if let Some(cond) = cond {
+                    self.resolve_expr(cond)?;
+                }
+                if let Some(increment) = increment {
+                    self.resolve_expr(increment)?;
+                }
+                self.resolve_stmt(body)?;
+                self.is_in_loop = enclosing_loop;
+                self.end_scope();
+            }
             StmtType::Break => {
                 if !self.is_in_loop {
                     return Err(ResolverError::BreakOutsideLoop(stmt.range.clone()));
@@ -123,9 +287,13 @@ impl Resolver {
     pub fn resolve_expr(&mut self, expr: &Expr) -> ResolverResult<()> {
         match *expr.intern {
             ExprType::Variable(ref name) => {
-                if let Some(scope) = self.scopes.last() {
-                    if let Some(&false) = scope.get(name) {
-                        println!("Cannot read local variable in its own initializer.");
+                // a global may refer to an earlier global of the same name
+                if let Some(scope) = self.scopes.last().filter(|_| self.scopes.len() > 1) {
+                    if let Some((false, _)) = scope.get(name) {
+                        return Err(ResolverError::ReadInOwnInitializer(
+                            name.clone(),
+                            expr.range,
+                        ));
                     }
                 }
                 self.resolve_local(expr, name);
@@ -162,6 +330,24 @@ impl Resolver {
                 self.resolve_expr(value)?;
                 self.resolve_expr(&set)?;
             }
+            ExprType::This => {
+                if let ClassType::None = self.current_class {
+                    return Err(ResolverError::ThisOutsideClass(expr.range));
+                }
+                self.resolve_local(expr, "this");
+            }
+            ExprType::Super(_) => {
+                match self.current_class {
+                    ClassType::None => {
+                        return Err(ResolverError::SuperOutsideClass(expr.range));
+                    }
+                    ClassType::Class => {
+                        return Err(ResolverError::SuperWithoutSuperclass(expr.range));
+                    }
+                    ClassType::Subclass => {}
+                }
+                self.resolve_local(expr, "super");
+            }
         }
         Ok(())
     }
@@ -176,9 +362,16 @@ impl Resolver {
         }
     }
 
-    fn resolve_function(&mut self, args: &[String], body: &Stmt) -> ResolverResult<()> {
+    fn resolve_function(
+        &mut self,
+        args: &[String],
+        body: &Stmt,
+        function_type: FunctionType,
+    ) -> ResolverResult<()> {
         let enclosing_function = self.current_function;
-        self.current_function = FunctionType::Function;
+        self.current_function = function_type;
+        // a loop around the declaration can't be left from inside the function
+        let enclosing_loop = std::mem::replace(&mut self.is_in_loop, false);
 
         self.begin_scope();
         for arg in args {
@@ -189,6 +382,7 @@ impl Resolver {
         self.end_scope();
 
         self.current_function = enclosing_function;
+        self.is_in_loop = enclosing_loop;
         Ok(())
     }
 
@@ -201,14 +395,20 @@ impl Resolver {
     }
 
     fn declare(&mut self, name: &str, range: &SourceCodeRange) -> ResolverResult<()> {
+        // globals may be redeclared, like in the reference implementation
+        let is_global = self.scopes.len() == 1;
         if let Some(scope) = self.scopes.last_mut() {
-            if scope.contains_key(name) {
-                return Err(ResolverError::DoubleDeclare(
-                    name.to_string(),
-                    range.clone(),
-                ));
-            } else {
-                scope.insert(name.to_string(), false);
+            match scope.get(name) {
+                Some((_, previous)) if !is_global => {
+                    return Err(ResolverError::DoubleDeclare(
+                        name.to_string(),
+                        range.clone(),
+                        *previous,
+                    ));
+                }
+                _ => {
+                    scope.insert(name.to_string(), (false, *range));
+                }
             }
         }
         Ok(())
@@ -216,7 +416,10 @@ impl Resolver {
 
     fn define(&mut self, name: &str) {
         if let Some(scope) = self.scopes.last_mut() {
-            scope.insert(name.to_string(), true);
+            let (defined, _) = scope
+                .entry(name.to_string())
+                .or_insert((false, SourceCodeRange::new(0)));
+            *defined = true;
         }
     }
 }
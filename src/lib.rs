@@ -0,0 +1,12 @@
+//! A Lox interpreter with a tree-walking and a bytecode backend.
+//!
+//! Use [`interpreter::Interpreter`] to embed Lox in a Rust program.
+pub mod backend;
+pub mod compiler;
+pub mod golden;
+pub mod interpreter;
+pub mod natives;
+pub mod output;
+pub mod vm;
+
+pub use interpreter::{Interpreter, InterpreterError, Literal};
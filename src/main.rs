@@ -1,23 +1,150 @@
-mod compiler;
-mod interpreter;
-mod vm;
-
 use std::env;
 use std::fs;
 use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use rlox::backend::{self, Backend, Outcome};
+use rlox::compiler::disassembler;
+use rlox::compiler::Compiler;
+use rlox::compiler::Program;
+use rlox::golden;
+use rlox::interpreter;
+use rlox::interpreter::diagnostic::Diagnostic;
+use rlox::interpreter::lexer::tokenize;
+use rlox::vm::VM;
+
+mod repl;
+
+fn report_diagnostics(diagnostics: &[Diagnostic], source: &str, json: bool) {
+    for diagnostic in diagnostics {
+        if json {
+            eprintln!("{}", diagnostic.to_json());
+        } else {
+            eprint!("{}", diagnostic.render(source));
+        }
+    }
+}
 
-use compiler::disassembler;
-use compiler::Compiler;
-use interpreter::lexer::tokenize;
-use vm::VM;
+/// Compiles `input` to bytecode, exiting with the error code of the failure
+/// if it doesn't compile.
+fn compile(input: &str, json: bool, optimize: bool) -> Program {
+    let mut compiler = Compiler::new();
+    match compiler.compile(input) {
+        Ok(()) => {
+            let mut program = compiler.into_program();
+            if optimize {
+                program.optimize();
+            }
+            program
+        }
+        Err(err) => {
+            report_diagnostics(&err.diagnostics(), input, json);
+            std::process::exit(err.status());
+        }
+    }
+}
+
+/// Describes how the runs of the tree-walker and the VM differ, one
+/// difference per entry.
+fn differences(tree: &Outcome, vm: &Outcome) -> Vec<String> {
+    let mut differences = Vec::new();
+    if tree.output != vm.output {
+        // the first line the two disagree on
+        let same = tree
+            .output
+            .lines()
+            .zip(vm.output.lines())
+            .take_while(|(tree, vm)| tree == vm)
+            .count();
+        let printed = |outcome: &Outcome| match outcome.output.lines().nth(same) {
+            Some(line) => format!("{:?}", line),
+            None => "nothing".to_string(),
+        };
+        if tree.output.lines().count() == same && vm.output.lines().count() == same {
+            differences.push("stdout differs in its trailing newlines".to_string());
+        } else {
+            differences.push(format!(
+                "stdout differs at line {}: tree printed {}, vm printed {}",
+                same + 1,
+                printed(tree),
+                printed(vm)
+            ));
+        }
+    }
+    if tree.status != vm.status {
+        differences.push(format!(
+            "exit status differs: tree exited with {}, vm with {}",
+            tree.status, vm.status
+        ));
+    }
+    let tree_line = tree.errors.first().map(|error| error.line);
+    let vm_line = vm.errors.first().map(|error| error.line);
+    if tree_line != vm_line {
+        let describe = |outcome: &Outcome| match outcome.errors.first() {
+            Some(error) => match error.line {
+                Some(line) => format!("line {} ({})", line, error.message),
+                None => format!("no line ({})", error.message),
+            },
+            None => "no error".to_string(),
+        };
+        differences.push(format!(
+            "error line differs: tree reported {}, vm {}",
+            describe(tree),
+            describe(vm)
+        ));
+    }
+    differences
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    // `--json` switches diagnostics to one JSON object per line
+    let json = args.iter().any(|arg| arg == "--json");
+    args.retain(|arg| arg != "--json");
+    // `--stress-gc` makes the VM collect garbage after every allocation
+    let stress_gc = args.iter().any(|arg| arg == "--stress-gc");
+    args.retain(|arg| arg != "--stress-gc");
+    // `-O` runs the peephole optimizer over the compiled bytecode
+    let optimize = args.iter().any(|arg| arg == "-O");
+    args.retain(|arg| arg != "-O");
+    // `--trace` prints the bytecode and every instruction the VM executes
+    let trace = args.iter().any(|arg| arg == "--trace");
+    args.retain(|arg| arg != "--trace");
+    // `--backend tree|vm` picks the engine `run` uses
+    let backend = match args.iter().position(|arg| arg == "--backend") {
+        Some(idx) => {
+            let backend = match args.get(idx + 1).map(|name| name.parse::<Backend>()) {
+                Some(Ok(backend)) => backend,
+                Some(Err(err)) => {
+                    eprintln!("{}", err);
+                    std::process::exit(64);
+                }
+                None => {
+                    eprintln!("Missing backend after --backend");
+                    std::process::exit(64);
+                }
+            };
+            args.drain(idx..idx + 2);
+            backend
+        }
+        None => Backend::Tree,
+    };
+    if trace && backend != Backend::Vm {
+        eprintln!("--trace only works with --backend vm");
+        std::process::exit(64);
+    }
+    if args.get(1).map(String::as_str) == Some("repl") {
+        if let Err(err) = repl::run(json) {
+            eprintln!("{}", err);
+            std::process::exit(74);
+        }
+        return;
+    }
     if args.len() < 3 {
         writeln!(
             io::stderr(),
-            "Usage: {} tokenize|parse|format|compile|evaluate|run|compile <filename>",
-            args[0]
+            "Usage: {} tokenize|parse|format|compile|evaluate|run|compile [--json] [--stress-gc] [-O] <filename>\n       {} run [--backend tree|vm] [--trace] <filename>\n       {} diff-backends [--stress-gc] [-O] <filename>\n       {} test [--stress-gc] [-O] <directory>\n       {} build [-O] <filename> [-o <output>]\n       {} exec [--stress-gc] <filename>\n       {} repl",
+            args[0], args[0], args[0], args[0], args[0], args[0], args[0]
         )
         .unwrap();
         return;
@@ -33,18 +160,17 @@ fn main() {
                 String::new()
             });
 
-            let tokens = tokenize(&file_contents);
-            match tokens {
-                Ok(tokens) => {
-                    for token in tokens {
-                        println!("{}", token);
-                    }
-                }
-                Err(()) => {
-                    println!("Failed to tokenize input");
-                    std::process::exit(65);
+            let (tokens, errors) = tokenize(&file_contents);
+            for token in tokens {
+                // error tokens are reported through the diagnostics below
+                if !matches!(token.inner, interpreter::token::TokenType::Error(_)) {
+                    println!("{}", token);
                 }
             }
+            if !errors.is_empty() {
+                report_diagnostics(&errors, &file_contents, json);
+                std::process::exit(65);
+            }
         }
         "parse" => {
             let file_contents = fs::read_to_string(filename).unwrap_or_else(|_| {
@@ -58,7 +184,7 @@ fn main() {
                     println!("{}", expr);
                 }
                 Err(err) => {
-                    eprintln!("{}", err);
+                    report_diagnostics(&err.diagnostics(), &file_contents, json);
                     std::process::exit(65);
                 }
             }
@@ -77,7 +203,7 @@ fn main() {
                     }
                 }
                 Err(err) => {
-                    eprintln!("{}", err);
+                    report_diagnostics(&err.diagnostics(), &file_contents, json);
                     std::process::exit(65);
                 }
             }
@@ -96,7 +222,7 @@ fn main() {
                     }
                 }
                 Err(err) => {
-                    eprintln!("{}", err);
+                    report_diagnostics(&err.diagnostics(), &file_contents, json);
                     std::process::exit(65);
                 }
             }
@@ -113,27 +239,40 @@ fn main() {
                     println!("{}", result);
                 }
                 Err(err) => {
-                    eprintln!("{}", err);
+                    report_diagnostics(&err.diagnostics(), &file_contents, json);
                     std::process::exit(70);
                 }
             }
         }
+        "run" if backend == Backend::Vm => {
+            let input = fs::read_to_string(filename).unwrap_or_else(|_| {
+                eprintln!("Failed to read file {}", filename);
+                std::process::exit(66);
+            });
+            let program = compile(&input, json, optimize);
+            if trace {
+                disassembler::disassemble_program(&program, filename);
+            }
+            let mut vm = VM::new(program);
+            if trace {
+                vm.enable_debug();
+            }
+            vm.set_gc_stress(stress_gc);
+            if let Err(err) = vm.run() {
+                report_diagnostics(&[Diagnostic::from(&err)], &input, json);
+                std::process::exit(70);
+            }
+        }
         "run" => {
             let file_contents = fs::read_to_string(filename).unwrap_or_else(|_| {
-                writeln!(io::stderr(), "Failed to read file {}", filename).unwrap();
-                String::new()
+                eprintln!("Failed to read file {}", filename);
+                std::process::exit(66);
             });
 
             let result = interpreter::run(&file_contents);
             if let Err(err) = result {
-                eprintln!("{}", err);
-                let code = match err {
-                    interpreter::InterpreterError::LexError => 65,
-                    interpreter::InterpreterError::ParseError(_) => 65,
-                    interpreter::InterpreterError::ResolverError(_) => 75,
-                    interpreter::InterpreterError::ExecError(_) => 70,
-                };
-                std::process::exit(code);
+                report_diagnostics(&err.diagnostics(), &file_contents, json);
+                std::process::exit(err.status());
             }
         }
         "compile" => {
@@ -141,13 +280,86 @@ fn main() {
                 writeln!(io::stderr(), "Failed to read file {}", filename).unwrap();
                 String::new()
             });
-            let mut compiler = Compiler::new();
-            compiler.compile(&input).unwrap();
-            let chunk = compiler.into_chunk();
-            disassembler::disassemble_chunk(&chunk, "test");
-            let mut vm = VM::new(chunk);
+            let program = compile(&input, json, optimize);
+            disassembler::disassemble_program(&program, "test");
+            let mut vm = VM::new(program);
             vm.enable_debug();
-            vm.run().unwrap();
+            vm.set_gc_stress(stress_gc);
+            if let Err(err) = vm.run() {
+                report_diagnostics(&[Diagnostic::from(&err)], &input, json);
+                std::process::exit(70);
+            }
+        }
+        "diff-backends" => {
+            let input = fs::read_to_string(filename).unwrap_or_else(|_| {
+                eprintln!("Failed to read file {}", filename);
+                std::process::exit(66);
+            });
+            let tree = backend::run(Backend::Tree, &input, optimize, stress_gc);
+            let vm = backend::run(Backend::Vm, &input, optimize, stress_gc);
+            let differences = differences(&tree, &vm);
+            if differences.is_empty() {
+                println!("Both backends agree");
+            } else {
+                for difference in &differences {
+                    println!("{}", difference);
+                }
+                std::process::exit(1);
+            }
+        }
+        "test" => {
+            let results =
+                golden::run_dir(Path::new(filename), optimize, stress_gc).unwrap_or_else(|err| {
+                    eprintln!("Failed to read tests from {}: {}", filename, err);
+                    std::process::exit(66);
+                });
+            let failed = results.iter().filter(|result| !result.passed()).count();
+            for result in results.iter().filter(|result| !result.passed()) {
+                println!("{}", result);
+            }
+            println!("{} passed, {} failed", results.len() - failed, failed);
+            if failed > 0 {
+                std::process::exit(1);
+            }
+        }
+        "build" => {
+            let input = fs::read_to_string(filename).unwrap_or_else(|_| {
+                eprintln!("Failed to read file {}", filename);
+                std::process::exit(66);
+            });
+            // `-o <file>` picks the output, next to the source by default
+            let output = match args.iter().position(|arg| arg == "-o") {
+                Some(idx) => match args.get(idx + 1) {
+                    Some(output) => PathBuf::from(output),
+                    None => {
+                        eprintln!("Missing file name after -o");
+                        std::process::exit(64);
+                    }
+                },
+                None => Path::new(filename).with_extension("loxc"),
+            };
+            let program = compile(&input, json, optimize);
+            if let Err(err) = fs::write(&output, program.to_bytes()) {
+                eprintln!("Failed to write file {}: {}", output.display(), err);
+                std::process::exit(74);
+            }
+        }
+        "exec" => {
+            let bytes = fs::read(filename).unwrap_or_else(|_| {
+                eprintln!("Failed to read file {}", filename);
+                std::process::exit(66);
+            });
+            let program = Program::from_bytes(&bytes).unwrap_or_else(|err| {
+                eprintln!("Failed to load {}: {}", filename, err);
+                std::process::exit(65);
+            });
+            let mut vm = VM::new(program);
+            vm.set_gc_stress(stress_gc);
+            if let Err(err) = vm.run() {
+                // the source isn't part of the file, so only the location is shown
+                report_diagnostics(&[Diagnostic::from(&err)], "", json);
+                std::process::exit(70);
+            }
         }
         _ => {
             writeln!(io::stderr(), "Unknown command: {}", command).unwrap();
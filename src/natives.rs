@@ -0,0 +1,301 @@
+use std::{fmt::Display, rc::Rc};
+
+/// Values passed between Lox code and native functions.
+///
+/// Both backends convert their own representation to and from this type, so a
+/// native function works the same no matter which backend calls it.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum NativeValue {
+    #[default]
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(String),
+}
+
+impl NativeValue {
+    fn type_name(&self) -> &'static str {
+        match self {
+            NativeValue::Nil => "nil",
+            NativeValue::Bool(_) => "bool",
+            NativeValue::Number(_) => "number",
+            NativeValue::String(_) => "string",
+        }
+    }
+}
+
+impl From<f64> for NativeValue {
+    fn from(n: f64) -> Self {
+        NativeValue::Number(n)
+    }
+}
+
+impl From<bool> for NativeValue {
+    fn from(b: bool) -> Self {
+        NativeValue::Bool(b)
+    }
+}
+
+impl From<String> for NativeValue {
+    fn from(s: String) -> Self {
+        NativeValue::String(s)
+    }
+}
+
+impl From<&str> for NativeValue {
+    fn from(s: &str) -> Self {
+        NativeValue::String(s.to_string())
+    }
+}
+
+impl From<()> for NativeValue {
+    fn from(_: ()) -> Self {
+        NativeValue::Nil
+    }
+}
+
+/// Conversion of a single native function argument into a Rust type.
+pub trait FromNative: Sized {
+    const TYPE_NAME: &'static str;
+
+    fn from_native(value: &NativeValue) -> Option<Self>;
+}
+
+impl FromNative for f64 {
+    const TYPE_NAME: &'static str = "number";
+
+    fn from_native(value: &NativeValue) -> Option<Self> {
+        match value {
+            NativeValue::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+impl FromNative for bool {
+    const TYPE_NAME: &'static str = "bool";
+
+    fn from_native(value: &NativeValue) -> Option<Self> {
+        match value {
+            NativeValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+impl FromNative for String {
+    const TYPE_NAME: &'static str = "string";
+
+    fn from_native(value: &NativeValue) -> Option<Self> {
+        match value {
+            NativeValue::String(s) => Some(s.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl FromNative for NativeValue {
+    const TYPE_NAME: &'static str = "value";
+
+    fn from_native(value: &NativeValue) -> Option<Self> {
+        Some(value.clone())
+    }
+}
+
+/// The number of arguments a native function accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn matches(&self, count: usize) -> bool {
+        match self {
+            Arity::Exact(n) => count == *n,
+            Arity::AtLeast(n) => count >= *n,
+        }
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Arity::Exact(n) => write!(f, "{}", n),
+            Arity::AtLeast(n) => write!(f, "{}..", n),
+        }
+    }
+}
+
+/// The arguments of a native function call, with typed accessors.
+pub struct NativeArgs<'a> {
+    name: &'a str,
+    values: &'a [NativeValue],
+}
+
+impl NativeArgs<'_> {
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Converts argument `idx` to `T`, failing with a message naming the
+    /// function and the argument if it is missing or has the wrong type.
+    pub fn get<T: FromNative>(&self, idx: usize) -> Result<T, String> {
+        let value = self
+            .values
+            .get(idx)
+            .ok_or_else(|| format!("'{}' is missing argument {}", self.name, idx + 1))?;
+        T::from_native(value).ok_or_else(|| {
+            format!(
+                "'{}' expected a {} as argument {} but got a {}",
+                self.name,
+                T::TYPE_NAME,
+                idx + 1,
+                value.type_name()
+            )
+        })
+    }
+
+    /// Like [`NativeArgs::get`], but returns `None` if the argument was not passed.
+    pub fn get_opt<T: FromNative>(&self, idx: usize) -> Result<Option<T>, String> {
+        if idx < self.values.len() {
+            self.get(idx).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+type NativeFn = dyn Fn(&NativeArgs) -> Result<NativeValue, String>;
+
+/// A Rust function callable from Lox.
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub(crate) name: String,
+    pub(crate) arity: Arity,
+    function: Rc<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new<R: Into<NativeValue>>(
+        name: &str,
+        arity: Arity,
+        function: impl Fn(&NativeArgs) -> Result<R, String> + 'static,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            arity,
+            function: Rc::new(move |args: &NativeArgs| function(args).map(Into::into)),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    /// Calls the function. The caller is responsible for checking the arity.
+    pub fn call(&self, args: &[NativeValue]) -> Result<NativeValue, String> {
+        (self.function)(&NativeArgs {
+            name: &self.name,
+            values: args,
+        })
+    }
+}
+
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.function, &other.function)
+    }
+}
+
+impl std::fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native {} fn>", self.name)
+    }
+}
+
+impl Display for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native {} fn>", self.name)
+    }
+}
+
+/// The set of native functions made available to a Lox program.
+///
+/// ```
+/// use rlox::natives::{Arity, Natives};
+///
+/// let mut natives = Natives::default();
+/// natives.register("hypot", Arity::Exact(2), |args| {
+///     Ok(args.get::<f64>(0)?.hypot(args.get::<f64>(1)?))
+/// });
+/// ```
+#[derive(Clone, Debug)]
+pub struct Natives {
+    functions: Vec<NativeFunction>,
+}
+
+impl Natives {
+    /// A registry without any functions, not even `clock`.
+    pub fn empty() -> Self {
+        Self {
+            functions: Vec::new(),
+        }
+    }
+
+    /// Registers `function` under `name`, replacing an earlier function of the same name.
+    pub fn register<R: Into<NativeValue>>(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        function: impl Fn(&NativeArgs) -> Result<R, String> + 'static,
+    ) -> &mut Self {
+        self.add(NativeFunction::new(name, arity, function))
+    }
+
+    pub fn add(&mut self, function: NativeFunction) -> &mut Self {
+        self.functions.retain(|f| f.name != function.name);
+        self.functions.push(function);
+        self
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &NativeFunction> {
+        self.functions.iter()
+    }
+}
+
+/// The standard natives: `clock()` and `syscall(name, ...)`.
+impl Default for Natives {
+    fn default() -> Self {
+        let mut natives = Self::empty();
+        natives.register("clock", Arity::Exact(0), |_| {
+            Ok(std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs_f64())
+        });
+        natives.register(
+            "syscall",
+            Arity::AtLeast(1),
+            |args| -> Result<NativeValue, String> {
+                let syscall = args.get::<String>(0)?;
+                match syscall.as_str() {
+                    "exit" => {
+                        let code = args.get_opt::<f64>(1)?.unwrap_or(0.0);
+                        std::process::exit(code as i32);
+                    }
+                    _ => Err(format!("Unknown syscall: {}", syscall)),
+                }
+            },
+        );
+        natives
+    }
+}
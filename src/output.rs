@@ -0,0 +1,130 @@
+use std::{cell::RefCell, fmt::Display, io::Write, rc::Rc};
+
+/// Where a Lox program's `print` output or diagnostics are written to.
+///
+/// Clones share the same destination.
+///
+/// ```
+/// use rlox::{output::Output, Interpreter};
+///
+/// let (output, captured) = Output::capture();
+/// let mut lox = Interpreter::new();
+/// lox.set_output(output);
+/// lox.run("print 1 + 2;").unwrap();
+/// assert_eq!(captured.contents(), "3\n");
+/// ```
+#[derive(Clone)]
+pub struct Output {
+    sink: Rc<RefCell<dyn Write>>,
+}
+
+impl Output {
+    pub fn stdout() -> Self {
+        Self::new(std::io::stdout())
+    }
+
+    pub fn stderr() -> Self {
+        Self::new(std::io::stderr())
+    }
+
+    /// Writes to any [`Write`] sink, e.g. a file or a socket.
+    pub fn new(sink: impl Write + 'static) -> Self {
+        Self {
+            sink: Rc::new(RefCell::new(sink)),
+        }
+    }
+
+    /// Calls `callback` with every line written, without the trailing newline.
+    pub fn callback(callback: impl FnMut(&str) + 'static) -> Self {
+        Self::new(CallbackWriter {
+            buffer: Vec::new(),
+            callback,
+        })
+    }
+
+    /// Collects everything written into memory.
+    pub fn capture() -> (Self, CapturedOutput) {
+        let buffer = Rc::new(RefCell::new(Vec::new()));
+        let output = Self::new(SharedBuffer(buffer.clone()));
+        (output, CapturedOutput(buffer))
+    }
+
+    pub(crate) fn write_line(&self, line: impl Display) -> std::io::Result<()> {
+        let mut sink = self.sink.borrow_mut();
+        sink.write_all(format!("{}\n", line).as_bytes())?;
+        sink.flush()
+    }
+
+    pub(crate) fn write_str(&self, text: &str) -> std::io::Result<()> {
+        let mut sink = self.sink.borrow_mut();
+        sink.write_all(text.as_bytes())?;
+        sink.flush()
+    }
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self::stdout()
+    }
+}
+
+impl PartialEq for Output {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.sink, &other.sink)
+    }
+}
+
+impl std::fmt::Debug for Output {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Output")
+    }
+}
+
+/// The text collected by an [`Output::capture`] sink.
+#[derive(Clone, Debug)]
+pub struct CapturedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl CapturedOutput {
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+
+    /// Returns the text collected so far and clears the buffer.
+    pub fn take(&self) -> String {
+        let bytes = std::mem::take(&mut *self.0.borrow_mut());
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+struct CallbackWriter<F: FnMut(&str)> {
+    buffer: Vec<u8>,
+    callback: F,
+}
+
+impl<F: FnMut(&str)> Write for CallbackWriter<F> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        while let Some(newline) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line = self.buffer.drain(..=newline).collect::<Vec<_>>();
+            (self.callback)(&String::from_utf8_lossy(&line[..newline]));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
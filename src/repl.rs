@@ -0,0 +1,141 @@
+use rlox::compiler::{disassembler, Compiler};
+use rlox::interpreter::{self, lexer::tokenize, token::TokenType, Interpreter};
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+const HELP: &str = "\
+Enter Lox code to run it. Expression values are echoed, and input continues
+on the next line until all braces and parentheses are closed.
+
+Commands:
+  :tokens <code>    show the tokens of <code>
+  :ast <code>       show the syntax tree of <code>
+  :bytecode <code>  show the bytecode <code> compiles to
+  :env              list the global variables
+  :help             show this message
+  :quit             leave the repl (or press Ctrl-D)";
+
+/// Reads Lox code line by line and runs it, keeping globals between lines.
+pub fn run(json: bool) -> rustyline::Result<()> {
+    let mut editor = DefaultEditor::new()?;
+    let history = history_file();
+    if let Some(history) = &history {
+        // there is no history yet on the first start
+        let _ = editor.load_history(history);
+    }
+
+    let mut lox = Interpreter::new();
+    let mut input = String::new();
+    loop {
+        let prompt = if input.is_empty() { "> " } else { "... " };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                // Ctrl-C abandons the current input, like in most shells
+                input.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err),
+        };
+        input.push_str(&line);
+        input.push('\n');
+        if is_incomplete(&input) {
+            continue;
+        }
+
+        let entry = std::mem::take(&mut input);
+        let entry = entry.trim();
+        if entry.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(entry)?;
+
+        if let Some(command) = entry.strip_prefix(':') {
+            let (command, argument) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+            match command {
+                "quit" | "q" => break,
+                "help" | "h" => println!("{}", HELP),
+                "tokens" => show_tokens(argument, json),
+                "ast" => show_ast(argument, json),
+                "bytecode" => show_bytecode(argument, json),
+                "env" => {
+                    for (name, value) in lox.globals() {
+                        println!("{} = {}", name, value);
+                    }
+                }
+                _ => eprintln!("Unknown command ':{}', try :help", command),
+            }
+            continue;
+        }
+
+        let source = with_semicolon(entry);
+        match lox.run_interactive(&source) {
+            Ok(Some(value)) => println!("{}", value),
+            Ok(None) => {}
+            Err(err) => crate::report_diagnostics(&err.diagnostics(), &source, json),
+        }
+    }
+
+    if let Some(history) = &history {
+        editor.save_history(history)?;
+    }
+    Ok(())
+}
+
+fn history_file() -> Option<std::path::PathBuf> {
+    std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".rlox_history"))
+}
+
+/// Whether `input` has unclosed braces or parentheses.
+fn is_incomplete(input: &str) -> bool {
+    let (tokens, _) = tokenize(input);
+    let mut depth = 0isize;
+    for token in tokens {
+        match token.inner {
+            TokenType::LeftBrace | TokenType::LeftParen => depth += 1,
+            TokenType::RightBrace | TokenType::RightParen => depth -= 1,
+            _ => {}
+        }
+    }
+    depth > 0
+}
+
+/// Lets a trailing expression be entered without its semicolon, e.g. `1 + 2`.
+fn with_semicolon(entry: &str) -> String {
+    if entry.ends_with(';') || entry.ends_with('}') {
+        entry.to_string()
+    } else {
+        format!("{};", entry)
+    }
+}
+
+fn show_tokens(source: &str, json: bool) {
+    let (tokens, errors) = tokenize(source);
+    for token in tokens {
+        if !matches!(token.inner, TokenType::Error(_)) {
+            println!("{}", token);
+        }
+    }
+    crate::report_diagnostics(&errors, source, json);
+}
+
+fn show_ast(source: &str, json: bool) {
+    let source = with_semicolon(source);
+    match interpreter::parse(&source) {
+        Ok(stmts) => {
+            for stmt in stmts {
+                println!("{}", stmt);
+            }
+        }
+        Err(err) => crate::report_diagnostics(&err.diagnostics(), &source, json),
+    }
+}
+
+fn show_bytecode(source: &str, json: bool) {
+    let source = with_semicolon(source);
+    let mut compiler = Compiler::new();
+    match compiler.compile(&source) {
+        Ok(()) => disassembler::disassemble_program(&compiler.into_program(), "repl"),
+        Err(err) => crate::report_diagnostics(&err.diagnostics(), &source, json),
+    }
+}
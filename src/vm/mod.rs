@@ -1,16 +1,43 @@
-use std::fmt::Display;
+use std::{collections::HashMap, fmt::Display, rc::Rc};
 
 use crate::{
-    compiler::{disassembler::disassemble_instruction, Chunk, OpCode, Value},
-    interpreter::SourceCodeRange,
+    compiler::{
+        disassembler::disassemble_instruction, BoundMethod, Chunk, Class, Closure, Function,
+        GlobalTable, Heap, Instance, ObjRef, Object, OpCode, Program, Upvalue, Value,
+    },
+    interpreter::{diagnostic::Diagnostic, SourceCodeRange},
+    natives::{Arity, NativeArgs, NativeFunction, NativeValue, Natives},
+    output::Output,
 };
 
-pub(crate) struct VM {
+/// How deeply calls may be nested before the VM reports a stack overflow.
+const MAX_FRAMES: usize = 10_000;
+
+pub struct VM {
     stack: Vec<Value>,
-    chunk: Chunk,
+    frames: Vec<CallFrame>,
+    /// Values of the globals by their slot, `None` until they are defined
+    globals: Vec<Option<Value>>,
+    global_names: GlobalTable,
+    /// Upvalues still pointing into the stack, shared by all closures capturing
+    /// the same variable
+    open_upvalues: Vec<ObjRef>,
+    heap: Heap,
+    /// The interned name of initializers
+    init_string: ObjRef,
+    debug: bool,
+    output: Output,
+}
+
+/// A function call that is being executed.
+struct CallFrame {
+    closure: ObjRef,
+    /// The closure's function, so instructions are read without going through the heap
+    function: Rc<Function>,
     /// Instruction Pointer. Points to the next instruction to be executed
     ip: usize,
-    debug: bool,
+    /// Index of the stack slot holding the called function, the locals follow it
+    slots: usize,
 }
 
 #[derive(Debug)]
@@ -21,12 +48,19 @@ pub struct InterpreterError {
 
 impl Display for InterpreterError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?} at line {}", self.error_type, self.range.line)
+        write!(f, "{}", Diagnostic::from(self))
+    }
+}
+
+impl From<&InterpreterError> for Diagnostic {
+    fn from(err: &InterpreterError) -> Self {
+        // the same code as runtime errors of the tree-walker
+        Diagnostic::error("E0001", err.error_type.to_string(), err.range)
     }
 }
 
 #[derive(Debug)]
-pub(crate) enum InterpretErrorType {
+pub enum InterpretErrorType {
     StackUnderflow,
     InvalidInstruction,
     InvalidData(String),
@@ -37,7 +71,7 @@ impl Display for InterpretErrorType {
         match self {
             InterpretErrorType::StackUnderflow => write!(f, "Stack underflow"),
             InterpretErrorType::InvalidInstruction => write!(f, "Invalid instruction"),
-            InterpretErrorType::InvalidData(data) => write!(f, "Invalid data: {}", data),
+            InterpretErrorType::InvalidData(message) => write!(f, "{}", message),
         }
     }
 }
@@ -49,54 +83,628 @@ impl InterpreterError {
 }
 
 impl VM {
-    pub fn new(chunk: Chunk) -> Self {
-        Self {
-            stack: Vec::new(),
-            chunk,
-            ip: 0,
+    /// Creates a VM for `program` with the standard native functions.
+    pub fn new(program: Program) -> Self {
+        Self::with_natives(program, &Natives::default())
+    }
+
+    /// Creates a VM for `program` whose only native functions are `natives`.
+    pub fn with_natives(program: Program, natives: &Natives) -> Self {
+        let mut heap = program.heap;
+        let function = Rc::new(Function {
+            name: String::new(),
+            arity: 0,
+            upvalue_count: 0,
+            chunk: program.chunk,
+        });
+        let function_obj = heap.alloc(Object::Function(function.clone()));
+        let script = heap.alloc(Object::Closure(Closure {
+            function: function_obj,
+            upvalues: Vec::new(),
+        }));
+        let init_string = heap.intern("init");
+        let mut vm = Self {
+            stack: vec![Value::Obj(script)],
+            frames: vec![CallFrame {
+                closure: script,
+                function,
+                ip: 0,
+                slots: 0,
+            }],
+            globals: vec![None; program.globals.len()],
+            global_names: program.globals,
+            open_upvalues: Vec::new(),
+            heap,
+            init_string,
             debug: false,
+            output: Output::default(),
+        };
+        for native in natives.iter() {
+            vm.define_native(native.clone());
         }
+        vm
     }
 
-    pub(crate) fn enable_debug(&mut self) {
+    /// Makes a Rust function available as a global under `name`.
+    pub fn register_native<R: Into<NativeValue>>(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        function: impl Fn(&NativeArgs) -> Result<R, String> + 'static,
+    ) {
+        self.define_native(NativeFunction::new(name, arity, function));
+    }
+
+    fn define_native(&mut self, native: NativeFunction) {
+        // a native the program never refers to can't be called anyway
+        if let Some(slot) = self.global_names.find(native.name()) {
+            let native = self.heap.alloc(Object::Native(native));
+            self.globals[slot as usize] = Some(Value::Obj(native));
+        }
+    }
+
+    /// Sends the output of `print` statements to `output` instead of stdout.
+    pub fn set_output(&mut self, output: Output) {
+        self.output = output;
+    }
+
+    pub fn enable_debug(&mut self) {
         self.debug = true;
     }
 
+    /// Collects garbage after every instruction that allocated instead of only
+    /// once enough memory was allocated, so objects that are freed too early
+    /// show up fast.
+    pub fn set_gc_stress(&mut self, stress: bool) {
+        self.heap.set_stress(stress);
+    }
+
+    /// The number of objects currently alive on the VM's heap.
+    pub fn heap_size(&self) -> usize {
+        self.heap.len()
+    }
+
+    /// Frees every object that the program can't reach anymore. The roots are
+    /// the stack, the globals and the closures being executed.
+    pub fn collect_garbage(&mut self) {
+        let values = self.stack.iter().chain(self.globals.iter().flatten());
+        let roots = values
+            .filter_map(|value| match value {
+                Value::Obj(obj) => Some(*obj),
+                _ => None,
+            })
+            .chain(self.frames.iter().map(|frame| frame.closure))
+            .chain(self.open_upvalues.iter().copied())
+            .chain(std::iter::once(self.init_string));
+        self.heap.collect(roots);
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().unwrap()
+    }
+
+    fn chunk(&self) -> &Chunk {
+        &self.frame().function.chunk
+    }
+
     fn read_byte(&mut self) -> u8 {
-        let byte = self.chunk.code_array[self.ip];
-        self.ip += 1;
+        let frame = self.frames.last_mut().unwrap();
+        let byte = frame.function.chunk.code_array[frame.ip];
+        frame.ip += 1;
         byte
     }
 
     fn read_constant(&mut self) -> Value {
         let idx = self.read_byte() as usize;
-        self.chunk.constant_pool[idx].clone()
+        self.chunk().constant_pool[idx]
     }
 
     fn read_constant_long(&mut self) -> Value {
-        let idx = (self.read_byte() as usize) << 16
-            | (self.read_byte() as usize) << 8
-            | self.read_byte() as usize;
-        self.chunk.constant_pool[idx].clone()
+        let idx = self.read_u24();
+        self.chunk().constant_pool[idx]
+    }
+
+    /// Reads the constant `op` refers to, with an index of one or three bytes
+    /// depending on its form.
+    fn read_constant_of(&mut self, op: OpCode) -> Value {
+        if op.is_long_constant() {
+            self.read_constant_long()
+        } else {
+            self.read_constant()
+        }
+    }
+
+    /// Reads the interned name of a class, property or method `op` refers to.
+    fn read_name(&mut self, op: OpCode) -> ObjRef {
+        match self.read_constant_of(op) {
+            Value::Obj(name) => name,
+            other => unreachable!("names are compiled to string constants, got {:?}", other),
+        }
     }
 
     fn runtime_error(&self, current_ip: usize, error: InterpretErrorType) -> InterpreterError {
-        InterpreterError::new(error, self.chunk.lines[current_ip])
+        InterpreterError::new(error, self.chunk().range(current_ip))
+    }
+
+    fn arity_error(
+        &self,
+        current_ip: usize,
+        name: &str,
+        arity: impl Display,
+        arg_count: usize,
+    ) -> InterpreterError {
+        self.runtime_error(
+            current_ip,
+            InterpretErrorType::InvalidData(format!(
+                "'{}' expected {} arguments but got {}",
+                name, arity, arg_count
+            )),
+        )
+    }
+
+    /// The instance on the heap that `value` refers to, if it is one.
+    fn as_instance(&self, value: Value) -> Option<&Instance> {
+        match value {
+            Value::Obj(obj) => match self.heap.get(obj) {
+                Object::Instance(instance) => Some(instance),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The class on the heap that `value` refers to, if it is one.
+    fn as_class(&self, value: Value) -> Option<ObjRef> {
+        match value {
+            Value::Obj(obj) if matches!(self.heap.get(obj), Object::Class(_)) => Some(obj),
+            _ => None,
+        }
+    }
+
+    /// The closure on the heap that `value` refers to, if it is one.
+    fn as_closure(&self, value: Value) -> Option<ObjRef> {
+        match value {
+            Value::Obj(obj) if matches!(self.heap.get(obj), Object::Closure(_)) => Some(obj),
+            _ => None,
+        }
+    }
+
+    /// The error for an operand of the wrong kind, which only bytecode that
+    /// wasn't produced by the compiler can contain.
+    fn expected(&self, current_ip: usize, kind: &str) -> InterpreterError {
+        self.runtime_error(
+            current_ip,
+            InterpretErrorType::InvalidData(format!("Expected {}", kind)),
+        )
+    }
+
+    /// Calls the value `arg_count` slots below the top of the stack.
+    fn call_value(&mut self, arg_count: usize, current_ip: usize) -> Result<(), InterpreterError> {
+        let callee_slot = self.stack.len() - arg_count - 1;
+        let callee = match self.stack[callee_slot] {
+            Value::Obj(callee) => self.heap.get(callee),
+            _ => return Err(self.not_callable(current_ip)),
+        };
+        match callee {
+            Object::Closure(_) => {
+                let Value::Obj(closure) = self.stack[callee_slot] else {
+                    unreachable!()
+                };
+                self.call_closure(closure, arg_count, current_ip)
+            }
+            Object::BoundMethod(bound) => {
+                let method = bound.method;
+                // the receiver takes the place of the callee as `this`
+                self.stack[callee_slot] = bound.receiver;
+                self.call_closure(method, arg_count, current_ip)
+            }
+            Object::Class(class) => {
+                let Value::Obj(class_obj) = self.stack[callee_slot] else {
+                    unreachable!()
+                };
+                let init = class.methods.get(&self.init_string).copied();
+                let arity = match init {
+                    Some(init) => {
+                        let function = self.heap.closure(init).function;
+                        self.heap.function(function).arity
+                    }
+                    None => 0,
+                };
+                if arity != arg_count {
+                    return Err(self.arity_error(current_ip, &class.name, arity, arg_count));
+                }
+                let instance = self.heap.alloc(Object::Instance(Instance {
+                    class: class_obj,
+                    fields: HashMap::new(),
+                }));
+                self.stack[callee_slot] = Value::Obj(instance);
+                match init {
+                    Some(init) => self.call_closure(init, arg_count, current_ip),
+                    None => Ok(()),
+                }
+            }
+            Object::Native(native) => {
+                if !native.arity().matches(arg_count) {
+                    return Err(self.arity_error(
+                        current_ip,
+                        native.name(),
+                        native.arity(),
+                        arg_count,
+                    ));
+                }
+                let result = self.stack[callee_slot + 1..]
+                    .iter()
+                    .map(|arg| self.heap.to_native(*arg))
+                    .collect::<Result<Vec<_>, _>>()
+                    .and_then(|args| native.call(&args))
+                    .map_err(|message| {
+                        self.runtime_error(current_ip, InterpretErrorType::InvalidData(message))
+                    })?;
+                let result = self.heap.native_to_value(result);
+                self.stack.truncate(callee_slot);
+                self.stack.push(result);
+                Ok(())
+            }
+            _ => Err(self.not_callable(current_ip)),
+        }
+    }
+
+    fn not_callable(&self, current_ip: usize) -> InterpreterError {
+        self.runtime_error(
+            current_ip,
+            InterpretErrorType::InvalidData("Can only call functions and classes".to_string()),
+        )
+    }
+
+    /// Starts executing `closure`, whose arguments are on top of the stack.
+    fn call_closure(
+        &mut self,
+        closure: ObjRef,
+        arg_count: usize,
+        current_ip: usize,
+    ) -> Result<(), InterpreterError> {
+        let function = self
+            .heap
+            .function(self.heap.closure(closure).function)
+            .clone();
+        if function.arity != arg_count {
+            return Err(self.arity_error(current_ip, &function.name, function.arity, arg_count));
+        }
+        if self.frames.len() == MAX_FRAMES {
+            return Err(self.runtime_error(
+                current_ip,
+                InterpretErrorType::InvalidData("Stack overflow.".to_string()),
+            ));
+        }
+        self.frames.push(CallFrame {
+            closure,
+            function,
+            ip: 0,
+            slots: self.stack.len() - arg_count - 1,
+        });
+        Ok(())
+    }
+
+    fn undefined_property(&self, current_ip: usize, name: ObjRef) -> InterpreterError {
+        self.runtime_error(
+            current_ip,
+            InterpretErrorType::InvalidData(format!(
+                "Undefined property '{}'.",
+                self.heap.string(name)
+            )),
+        )
+    }
+
+    /// Calls the method `name` of `class` on the receiver below the arguments.
+    fn invoke_from_class(
+        &mut self,
+        class: ObjRef,
+        name: ObjRef,
+        arg_count: usize,
+        current_ip: usize,
+    ) -> Result<(), InterpreterError> {
+        match self.heap.class(class).methods.get(&name).copied() {
+            Some(method) => self.call_closure(method, arg_count, current_ip),
+            None => Err(self.undefined_property(current_ip, name)),
+        }
+    }
+
+    /// Replaces the instance on top of the stack with its method `name`, bound to it.
+    fn bind_method(
+        &mut self,
+        class: ObjRef,
+        name: ObjRef,
+        current_ip: usize,
+    ) -> Result<(), InterpreterError> {
+        let Some(method) = self.heap.class(class).methods.get(&name).copied() else {
+            return Err(self.undefined_property(current_ip, name));
+        };
+        let receiver = self.stack.last_mut().unwrap();
+        let bound = self.heap.alloc(Object::BoundMethod(BoundMethod {
+            receiver: *receiver,
+            method,
+        }));
+        *receiver = Value::Obj(bound);
+        Ok(())
+    }
+
+    /// Returns the upvalue for the variable in stack slot `slot`, reusing the
+    /// open one if another closure already captured it.
+    fn capture_upvalue(&mut self, slot: usize) -> ObjRef {
+        let heap = &self.heap;
+        let existing = self
+            .open_upvalues
+            .iter()
+            .find(|upvalue| matches!(heap.upvalue(**upvalue), Upvalue::Open(s) if *s == slot));
+        if let Some(upvalue) = existing {
+            return *upvalue;
+        }
+        let upvalue = self.heap.alloc(Object::Upvalue(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue);
+        upvalue
+    }
+
+    /// Moves the variables in stack slots `from` and above into their upvalues,
+    /// as those slots are about to be discarded.
+    fn close_upvalues(&mut self, from: usize, current_ip: usize) -> Result<(), InterpreterError> {
+        let (heap, stack) = (&mut self.heap, &self.stack);
+        // bytecode not written by the compiler may pop a captured variable
+        // without closing its upvalue first
+        let mut lost = false;
+        self.open_upvalues.retain(|upvalue| {
+            let upvalue = heap.upvalue_mut(*upvalue);
+            match *upvalue {
+                Upvalue::Open(slot) if slot >= from => {
+                    match stack.get(slot) {
+                        Some(value) => *upvalue = Upvalue::Closed(*value),
+                        None => lost = true,
+                    }
+                    false
+                }
+                Upvalue::Open(_) => true,
+                Upvalue::Closed(_) => false,
+            }
+        });
+        if lost {
+            return Err(self.expected(current_ip, "captured variable on the stack"));
+        }
+        Ok(())
     }
 
     pub fn run(&mut self) -> Result<(), InterpreterError> {
         loop {
+            // between instructions every live object is reachable from a root
+            if self.heap.should_collect() {
+                self.collect_garbage();
+            }
             if self.debug {
-                println!("Stack: {:?}", self.stack);
-                disassemble_instruction(&self.chunk, self.ip);
+                let stack = self
+                    .stack
+                    .iter()
+                    .map(|value| self.heap.display(*value).to_string())
+                    .collect::<Vec<_>>();
+                println!("Stack: [{}]", stack.join(", "));
+                disassemble_instruction(
+                    self.chunk(),
+                    &self.heap,
+                    &self.global_names,
+                    self.frame().ip,
+                );
             }
-            let current_ip = self.ip;
+            let current_ip = self.frame().ip;
             let instruction: OpCode = self.read_byte().try_into().map_err(|_| {
                 self.runtime_error(current_ip, InterpretErrorType::InvalidInstruction)
             })?;
             use OpCode::*;
             match instruction {
-                OpReturn => return Ok(()),
+                OpReturn => {
+                    let result = self.stack.pop().ok_or_else(|| {
+                        self.runtime_error(current_ip, InterpretErrorType::StackUnderflow)
+                    })?;
+                    // the stack slots of the returning function are not needed anymore
+                    self.close_upvalues(self.frame().slots, current_ip)?;
+                    let frame = self.frames.pop().unwrap();
+                    self.stack.truncate(frame.slots);
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.stack.push(result);
+                }
+                OpCall => {
+                    let arg_count = self.read_byte() as usize;
+                    self.call_value(arg_count, current_ip)?;
+                }
+                OpClosure | OpClosureLong => {
+                    let function = match self.read_constant_of(instruction) {
+                        Value::Obj(obj) if matches!(self.heap.get(obj), Object::Function(_)) => obj,
+                        _ => {
+                            return Err(self.runtime_error(
+                                current_ip,
+                                InterpretErrorType::InvalidData("Expected function".to_string()),
+                            ))
+                        }
+                    };
+                    let upvalue_count = self.heap.function(function).upvalue_count;
+                    let mut upvalues = Vec::with_capacity(upvalue_count);
+                    for _ in 0..upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let idx = self.read_u16() as usize;
+                        upvalues.push(if is_local {
+                            self.capture_upvalue(self.frame().slots + idx)
+                        } else {
+                            self.heap.closure(self.frame().closure).upvalues[idx]
+                        });
+                    }
+                    let closure = self
+                        .heap
+                        .alloc(Object::Closure(Closure { function, upvalues }));
+                    self.stack.push(Value::Obj(closure));
+                }
+                OpGetUpvalue => {
+                    let idx = self.read_byte() as usize;
+                    let upvalue = self.heap.closure(self.frame().closure).upvalues[idx];
+                    let value = match self.heap.upvalue(upvalue) {
+                        Upvalue::Open(slot) => *self.stack.get(*slot).ok_or_else(|| {
+                            self.expected(current_ip, "captured variable on the stack")
+                        })?,
+                        Upvalue::Closed(value) => *value,
+                    };
+                    self.stack.push(value);
+                }
+                OpSetUpvalue => {
+                    let idx = self.read_byte() as usize;
+                    let value = *self.stack.last().ok_or_else(|| {
+                        self.runtime_error(current_ip, InterpretErrorType::StackUnderflow)
+                    })?;
+                    let upvalue = self.heap.closure(self.frame().closure).upvalues[idx];
+                    match self.heap.upvalue_mut(upvalue) {
+                        Upvalue::Open(slot) => match self.stack.get_mut(*slot) {
+                            Some(variable) => *variable = value,
+                            None => {
+                                return Err(
+                                    self.expected(current_ip, "captured variable on the stack")
+                                )
+                            }
+                        },
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1, current_ip)?;
+                    self.stack.pop();
+                }
+                OpClass | OpClassLong => {
+                    let name = self.read_name(instruction);
+                    let class = self.heap.alloc(Object::Class(Class {
+                        name: self.heap.string(name).to_string(),
+                        methods: HashMap::new(),
+                    }));
+                    self.stack.push(Value::Obj(class));
+                }
+                OpInherit => {
+                    let (Some(subclass), Some(superclass)) =
+                        (self.stack.pop(), self.stack.last().copied())
+                    else {
+                        return Err(
+                            self.runtime_error(current_ip, InterpretErrorType::StackUnderflow)
+                        );
+                    };
+                    let Some(superclass) = self.as_class(superclass) else {
+                        return Err(self.runtime_error(
+                            current_ip,
+                            InterpretErrorType::InvalidData(
+                                "Superclass must be a class".to_string(),
+                            ),
+                        ));
+                    };
+                    let subclass = self
+                        .as_class(subclass)
+                        .ok_or_else(|| self.expected(current_ip, "class"))?;
+                    // methods defined later in the subclass override the copies
+                    let methods = self.heap.class(superclass).methods.clone();
+                    self.heap.class_mut(subclass).methods.extend(methods);
+                }
+                OpMethod | OpMethodLong => {
+                    let name = self.read_name(instruction);
+                    let (Some(method), Some(class)) =
+                        (self.stack.pop(), self.stack.last().copied())
+                    else {
+                        return Err(
+                            self.runtime_error(current_ip, InterpretErrorType::StackUnderflow)
+                        );
+                    };
+                    let method = self
+                        .as_closure(method)
+                        .ok_or_else(|| self.expected(current_ip, "closure"))?;
+                    let class = self
+                        .as_class(class)
+                        .ok_or_else(|| self.expected(current_ip, "class"))?;
+                    self.heap.class_mut(class).methods.insert(name, method);
+                }
+                OpGetProperty | OpGetPropertyLong => {
+                    let name = self.read_name(instruction);
+                    let receiver = *self.stack.last().ok_or_else(|| {
+                        self.runtime_error(current_ip, InterpretErrorType::StackUnderflow)
+                    })?;
+                    let Some(instance) = self.as_instance(receiver) else {
+                        return Err(self.runtime_error(
+                            current_ip,
+                            InterpretErrorType::InvalidData(
+                                "Only instances have properties".to_string(),
+                            ),
+                        ));
+                    };
+                    // fields shadow methods
+                    if let Some(value) = instance.fields.get(&name).copied() {
+                        *self.stack.last_mut().unwrap() = value;
+                    } else {
+                        self.bind_method(instance.class, name, current_ip)?;
+                    }
+                }
+                OpSetProperty | OpSetPropertyLong => {
+                    let name = self.read_name(instruction);
+                    let value = self.stack.pop().ok_or_else(|| {
+                        self.runtime_error(current_ip, InterpretErrorType::StackUnderflow)
+                    })?;
+                    let instance = match self.stack.pop() {
+                        Some(Value::Obj(obj)) => match self.heap.get_mut(obj) {
+                            Object::Instance(instance) => Some(instance),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    let Some(instance) = instance else {
+                        return Err(self.runtime_error(
+                            current_ip,
+                            InterpretErrorType::InvalidData(
+                                "Only instances have fields".to_string(),
+                            ),
+                        ));
+                    };
+                    instance.fields.insert(name, value);
+                    self.stack.push(value);
+                }
+                OpInvoke | OpInvokeLong => {
+                    let name = self.read_name(instruction);
+                    let arg_count = self.read_byte() as usize;
+                    let receiver_slot = self.stack.len() - arg_count - 1;
+                    let Some(instance) = self.as_instance(self.stack[receiver_slot]) else {
+                        return Err(self.runtime_error(
+                            current_ip,
+                            InterpretErrorType::InvalidData(
+                                "Only instances have properties".to_string(),
+                            ),
+                        ));
+                    };
+                    if let Some(field) = instance.fields.get(&name).copied() {
+                        // a field holding a function is called like any other value
+                        self.stack[receiver_slot] = field;
+                        self.call_value(arg_count, current_ip)?;
+                    } else {
+                        self.invoke_from_class(instance.class, name, arg_count, current_ip)?;
+                    }
+                }
+                OpGetSuper | OpGetSuperLong => {
+                    let name = self.read_name(instruction);
+                    let superclass = self.stack.pop().ok_or_else(|| {
+                        self.runtime_error(current_ip, InterpretErrorType::StackUnderflow)
+                    })?;
+                    let superclass = self
+                        .as_class(superclass)
+                        .ok_or_else(|| self.expected(current_ip, "class"))?;
+                    self.bind_method(superclass, name, current_ip)?;
+                }
+                OpSuperInvoke | OpSuperInvokeLong => {
+                    let name = self.read_name(instruction);
+                    let arg_count = self.read_byte() as usize;
+                    let superclass = self.stack.pop().ok_or_else(|| {
+                        self.runtime_error(current_ip, InterpretErrorType::StackUnderflow)
+                    })?;
+                    let superclass = self
+                        .as_class(superclass)
+                        .ok_or_else(|| self.expected(current_ip, "class"))?;
+                    self.invoke_from_class(superclass, name, arg_count, current_ip)?;
+                }
                 OpConstant => {
                     let constant = self.read_constant();
                     self.stack.push(constant);
@@ -110,7 +718,17 @@ impl VM {
                 OpTrue => self.stack.push(Value::Bool(true)),
                 OpPrint => {
                     if let Some(val) = self.stack.pop() {
-                        println!("{}", val);
+                        self.output
+                            .write_line(self.heap.display(val))
+                            .map_err(|err| {
+                                self.runtime_error(
+                                    current_ip,
+                                    InterpretErrorType::InvalidData(format!(
+                                        "Failed to write output: {}",
+                                        err
+                                    )),
+                                )
+                            })?;
                     } else {
                         return Err(
                             self.runtime_error(current_ip, InterpretErrorType::StackUnderflow)
@@ -123,58 +741,60 @@ impl VM {
                     })?;
                 }
                 OpDefineGlobal => {
-                    let mut pointer_address = 0;
-                    for i in 0..std::mem::size_of::<usize>() {
-                        pointer_address |= (self.read_byte() as usize) << (i * 8);
-                    }
-                    let value = self.stack.last().ok_or_else(|| {
+                    let slot = self.read_u16();
+                    // the value is not needed on the stack anymore, and leaving it
+                    // there would shift the slots of all locals
+                    let value = self.stack.pop().ok_or_else(|| {
                         self.runtime_error(current_ip, InterpretErrorType::StackUnderflow)
                     })?;
-                    let key = unsafe { std::mem::transmute::<usize, ustr::Ustr>(pointer_address) };
-                    if let Some(_) = self.chunk.globals.get(&key) {
-                        return Err(self.runtime_error(
-                            current_ip,
-                            InterpretErrorType::InvalidData("Global already defined".to_string()),
-                        ));
-                    }
-                    self.chunk.globals.insert(key, value.clone());
+                    // like in the tree-walker, a global can be declared again
+                    *self.global(slot, current_ip)? = Some(value);
                 }
                 OpGetGlobal => {
-                    let pointer_address = self.read_pointer();
-                    let ustring =
-                        unsafe { std::mem::transmute::<usize, ustr::Ustr>(pointer_address) };
-                    if let Some(value) = self.chunk.globals.get(&ustring) {
-                        self.stack.push(value.clone());
-                    } else {
-                        return Err(self.runtime_error(
-                            current_ip,
-                            InterpretErrorType::InvalidData("Global not found".to_string()),
-                        ));
+                    let slot = self.read_u16();
+                    match *self.global(slot, current_ip)? {
+                        Some(value) => self.stack.push(value),
+                        None => return Err(self.global_error(current_ip, slot, "not found")),
                     }
                 }
                 OpSetGlobal => {
-                    let pointer_address = self.read_pointer();
-                    let value = self.stack.last().ok_or_else(|| {
+                    let slot = self.read_u16();
+                    let value = *self.stack.last().ok_or_else(|| {
                         self.runtime_error(current_ip, InterpretErrorType::StackUnderflow)
                     })?;
-                    self.chunk.globals.insert(
-                        unsafe { std::mem::transmute::<usize, ustr::Ustr>(pointer_address) },
-                        value.clone(),
-                    );
+                    // assignment never declares a global
+                    if self.global(slot, current_ip)?.is_none() {
+                        return Err(self.runtime_error(
+                            current_ip,
+                            InterpretErrorType::InvalidData(format!(
+                                "Can't assign to undefined global variable '{}'",
+                                self.global_names.name(slot)
+                            )),
+                        ));
+                    }
+                    *self.global(slot, current_ip)? = Some(value);
                 }
-                OpGetLocal => {
-                    let idx = self.read_byte() as usize;
-                    let value = self.stack.get(idx).ok_or_else(|| {
+                OpGetLocal | OpGetLocalLong => {
+                    let slot = match instruction {
+                        OpGetLocal => self.read_byte() as usize,
+                        _ => self.read_u16() as usize,
+                    };
+                    let idx = self.frame().slots + slot;
+                    let value = *self.stack.get(idx).ok_or_else(|| {
                         self.runtime_error(current_ip, InterpretErrorType::StackUnderflow)
                     })?;
-                    self.stack.push(value.clone());
+                    self.stack.push(value);
                 }
-                OpSetLocal => {
-                    let idx = self.read_byte() as usize;
-                    let value = self.stack.last().ok_or_else(|| {
+                OpSetLocal | OpSetLocalLong => {
+                    let slot = match instruction {
+                        OpSetLocal => self.read_byte() as usize,
+                        _ => self.read_u16() as usize,
+                    };
+                    let idx = self.frame().slots + slot;
+                    let value = *self.stack.last().ok_or_else(|| {
                         self.runtime_error(current_ip, InterpretErrorType::StackUnderflow)
                     })?;
-                    self.stack[idx] = value.clone();
+                    self.stack[idx] = value;
                 }
                 OpNegate => {
                     if let Value::Number(num) = self.stack.pop().ok_or_else(|| {
@@ -184,7 +804,9 @@ impl VM {
                     } else {
                         return Err(self.runtime_error(
                             current_ip,
-                            InterpretErrorType::InvalidData("Expected number".to_string()),
+                            InterpretErrorType::InvalidData(
+                                "Unary minus expects a number".to_string(),
+                            ),
                         ));
                     }
                 }
@@ -195,7 +817,7 @@ impl VM {
                     let bool: bool = (&val).into();
                     self.stack.push(Value::Bool(!bool));
                 }
-                OpEq => {
+                OpEq | OpNotEqual => {
                     let (a, b) = (
                         self.stack.pop().ok_or_else(|| {
                             self.runtime_error(current_ip, InterpretErrorType::StackUnderflow)
@@ -204,9 +826,10 @@ impl VM {
                             self.runtime_error(current_ip, InterpretErrorType::StackUnderflow)
                         })?,
                     );
-                    self.stack.push(Value::Bool(a == b));
+                    self.stack
+                        .push(Value::Bool((a == b) == matches!(instruction, OpEq)));
                 }
-                OpLess | OpGreater => {
+                OpLess | OpGreater | OpGreaterEqual | OpLessEqual => {
                     if let (Value::Number(b), Value::Number(a)) = (
                         self.stack.pop().ok_or_else(|| {
                             self.runtime_error(current_ip, InterpretErrorType::StackUnderflow)
@@ -215,17 +838,21 @@ impl VM {
                             self.runtime_error(current_ip, InterpretErrorType::StackUnderflow)
                         })?,
                     ) {
+                        // the fused comparisons negate, like the instructions they
+                        // replace, so they give the same result for NaN
+                        #[allow(clippy::neg_cmp_op_on_partial_ord)]
                         let result = match instruction {
-                            OpEq => a == b,
                             OpLess => a < b,
                             OpGreater => a > b,
+                            OpGreaterEqual => !(a < b),
+                            OpLessEqual => !(a > b),
                             _ => unreachable!(),
                         };
                         self.stack.push(Value::Bool(result));
                     } else {
                         return Err(self.runtime_error(
                             current_ip,
-                            InterpretErrorType::InvalidData("Expected number".to_string()),
+                            InterpretErrorType::InvalidData("Operands must be numbers".to_string()),
                         ));
                     }
                 }
@@ -248,24 +875,20 @@ impl VM {
                             };
                             self.stack.push(Value::Number(result));
                         }
-                        (b, Value::String(a)) => {
-                            let result = match instruction {
-                                OpAdd => a + &b.to_string(),
-                                _ => {
-                                    return Err(self.runtime_error(
-                                        current_ip,
-                                        InterpretErrorType::InvalidData(
-                                            "Invalid operation on strings".to_string(),
-                                        ),
-                                    ));
-                                }
-                            };
-                            self.stack.push(Value::String(result));
+                        (Value::Obj(b), Value::Obj(a))
+                            if matches!(self.heap.get(a), Object::String(_))
+                                && matches!(self.heap.get(b), Object::String(_)) =>
+                        {
+                            let result = format!("{}{}", self.heap.string(a), self.heap.string(b));
+                            let result = self.heap.intern(&result);
+                            self.stack.push(Value::Obj(result));
                         }
                         _ => {
                             return Err(self.runtime_error(
                                 current_ip,
-                                InterpretErrorType::InvalidData("Expected number".to_string()),
+                                InterpretErrorType::InvalidData(
+                                    "Operands must be two numbers or two strings".to_string(),
+                                ),
                             ));
                         }
                     }
@@ -290,41 +913,70 @@ impl VM {
                     } else {
                         return Err(self.runtime_error(
                             current_ip,
-                            InterpretErrorType::InvalidData("Expected number".to_string()),
+                            InterpretErrorType::InvalidData("Operands must be numbers".to_string()),
                         ));
                     }
                 }
-                OpJumpIfFalse => {
-                    let jump = self.read_u16();
+                OpJumpIfFalse | OpJumpIfFalseLong => {
+                    let jump = match instruction {
+                        OpJumpIfFalse => self.read_u16() as usize,
+                        _ => self.read_u24(),
+                    };
                     let condition = self.stack.last().ok_or_else(|| {
                         self.runtime_error(current_ip, InterpretErrorType::StackUnderflow)
                     })?;
                     if !bool::from(condition) {
-                        self.ip += jump as usize;
+                        self.frames.last_mut().unwrap().ip += jump;
                     }
                 }
-                OpJump => {
-                    let jump = self.read_u16();
-                    self.ip += jump as usize;
+                OpJump | OpJumpLong => {
+                    let jump = match instruction {
+                        OpJump => self.read_u16() as usize,
+                        _ => self.read_u24(),
+                    };
+                    self.frames.last_mut().unwrap().ip += jump;
                 }
-                OpLoop => {
-                    let jump = self.read_u16();
-                    self.ip -= jump as usize;
+                OpLoop | OpLoopLong => {
+                    let jump = match instruction {
+                        OpLoop => self.read_u16() as usize,
+                        _ => self.read_u24(),
+                    };
+                    self.frames.last_mut().unwrap().ip -= jump;
                 }
             }
         }
     }
 
-    fn read_pointer(&mut self) -> usize {
-        let mut pointer_address = 0;
-        for i in 0..std::mem::size_of::<usize>() {
-            pointer_address |= (self.read_byte() as usize) << (i * 8);
+    /// The global in `slot`, which only bytecode that didn't come from the
+    /// compiler may lack.
+    fn global(
+        &mut self,
+        slot: u16,
+        current_ip: usize,
+    ) -> Result<&mut Option<Value>, InterpreterError> {
+        if slot as usize >= self.globals.len() {
+            return Err(self.runtime_error(current_ip, InterpretErrorType::InvalidInstruction));
         }
-        pointer_address
+        Ok(&mut self.globals[slot as usize])
+    }
+
+    fn global_error(&self, current_ip: usize, slot: u16, problem: &str) -> InterpreterError {
+        self.runtime_error(
+            current_ip,
+            InterpretErrorType::InvalidData(format!(
+                "Global '{}' {}",
+                self.global_names.name(slot),
+                problem
+            )),
+        )
     }
 
     fn read_u16(&mut self) -> u16 {
         let u16 = (self.read_byte() as u16) << 8 | self.read_byte() as u16;
         u16
     }
+
+    fn read_u24(&mut self) -> usize {
+        (self.read_byte() as usize) << 16 | (self.read_u16() as usize)
+    }
 }
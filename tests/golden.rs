@@ -0,0 +1,29 @@
+use std::path::Path;
+
+use rlox::golden;
+
+/// Runs every script in `tests/lox` on both backends, see [`rlox::golden`]
+/// for the annotations they use.
+#[test]
+fn lox_scripts() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox");
+    // the last run looks for objects the VM still uses but the collector misses
+    for (optimize, stress_gc) in [(false, false), (true, false), (false, true)] {
+        let results = golden::run_dir(&dir, optimize, stress_gc).unwrap();
+        assert!(!results.is_empty(), "no scripts in {}", dir.display());
+        let failures = results
+            .iter()
+            .filter(|result| !result.passed())
+            .map(|result| result.to_string())
+            .collect::<Vec<_>>();
+        assert!(
+            failures.is_empty(),
+            "{} of {} runs failed (optimize: {}, stress_gc: {}):\n{}",
+            failures.len(),
+            results.len(),
+            optimize,
+            stress_gc,
+            failures.join("\n")
+        );
+    }
+}
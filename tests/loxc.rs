@@ -0,0 +1,30 @@
+use std::{fs, path::Path};
+
+use rlox::{compiler::Program, output::Output, vm::VM};
+
+/// The files in `tests/loxc` were built from a valid script and then damaged
+/// in a way the verifier can't see, as it doesn't know the types of values on
+/// the stack. They used to panic the VM, now they have to fail with an error.
+#[test]
+fn damaged_bytecode_fails_cleanly() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/loxc");
+    let mut files = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "loxc")
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    assert!(!files.is_empty(), "no files in {}", dir.display());
+
+    for path in files {
+        let bytes = fs::read(&path).unwrap();
+        let program = Program::from_bytes(&bytes)
+            .unwrap_or_else(|err| panic!("{} doesn't load: {}", path.display(), err));
+        let mut vm = VM::new(program);
+        vm.set_output(Output::capture().0);
+        assert!(vm.run().is_err(), "{} ran successfully", path.display());
+    }
+}